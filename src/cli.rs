@@ -0,0 +1,205 @@
+use super::{dictionary::Filter, storage};
+use std::{
+    io::{self, Error, ErrorKind},
+    net::ToSocketAddrs,
+    str::FromStr,
+};
+
+pub const USAGE: &str = "\
+tip - a terminal based typing game
+
+USAGE:
+    tip [OPTIONS] [COMMAND]
+
+COMMANDS:
+    play             Start a Single Player run right away
+    host             Create a new multiplayer session
+    join <code>      Join a multiplayer session
+    stats            Print the high scores and recent runs
+    serve [addr]     Run a session server (default 0.0.0.0:8080)
+
+    Without a command the main menu is shown.
+
+OPTIONS:
+    -s, --server <addr>       Session server to connect to (default 127.0.0.1:8080)
+        --mode <mode>         single or multi, same as the play and host commands
+        --seed <number>       Seed for Single Player runs, replays a run
+        --speed <factor>      How fast the words move in Single Player (default 1.0)
+        --adaptive            Match the Single Player pace to your typing instead of levels
+        --dict <path>         Word list with one word per line, - reads it from stdin
+        --min-length <n>      Skip words shorter than n characters
+        --max-length <n>      Skip words longer than n characters
+        --chars <chars>       Only use words made up of these characters
+        --difficulty <tier>   easy, medium or hard
+        --skew <factor>       Prefer common words, 0 is uniform (default 0)
+        --punctuation         Capitalize words and add punctuation like in real text
+    -h, --help                Print this help
+    -V, --version             Print the version
+
+Defaults for most options can be set in ~/.config/tip/config.toml
+(or the file in $TIP_CONFIG) and with TIP_SERVER, TIP_NICKNAME,
+TIP_SPEED and TIP_DICT, command line options take precedence.
+";
+
+pub enum Command {
+    Menu,
+    Play,
+    Host,
+    Join(String),
+    Stats,
+    Serve(String),
+    Help,
+    Version,
+}
+
+pub struct Args {
+    pub command: Command,
+    pub server: Option<String>,
+    pub seed: Option<u64>,
+    pub speed: Option<f32>,
+    pub adaptive: bool,
+    pub dict: Option<String>,
+    pub filter: Filter,
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidInput, message.into())
+}
+
+fn parse_value<T: FromStr>(arg: &str, value: String) -> io::Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("Invalid value '{}' for {}.", value, arg)))
+}
+
+/// Parses the command line arguments, without the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> io::Result<Args> {
+    let mut args = args.into_iter();
+
+    let mut parsed = Args {
+        command: Command::Menu,
+        server: None,
+        seed: None,
+        speed: None,
+        adaptive: false,
+        dict: None,
+        filter: Filter::default(),
+    };
+
+    let mut command: Option<String> = None;
+    let mut operands: Vec<String> = vec![];
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| invalid(format!("{} needs a value.", arg)))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => parsed.command = Command::Help,
+            "-V" | "--version" => parsed.command = Command::Version,
+            "-s" | "--server" => parsed.server = Some(value()?),
+            "--mode" => {
+                let mode = value()?;
+                command = Some(match mode.as_str() {
+                    "single" => "play".to_owned(),
+                    "multi" => "host".to_owned(),
+                    _ => {
+                        return Err(invalid(format!(
+                            "Unknown mode '{}', expected single or multi.",
+                            mode
+                        )))
+                    }
+                });
+            }
+            "--seed" => parsed.seed = Some(parse_value(&arg, value()?)?),
+            "--speed" => parsed.speed = Some(parse_value(&arg, value()?)?),
+            "--adaptive" => parsed.adaptive = true,
+            "--dict" => parsed.dict = Some(value()?),
+            "--min-length" => parsed.filter.min_length = Some(parse_value(&arg, value()?)?),
+            "--max-length" => parsed.filter.max_length = Some(parse_value(&arg, value()?)?),
+            "--chars" => parsed.filter.chars = Some(value()?),
+            "--difficulty" => parsed.filter.difficulty = Some(value()?.parse()?),
+            "--skew" => parsed.filter.skew = Some(parse_value(&arg, value()?)?),
+            "--punctuation" => parsed.filter.punctuation = Some(true),
+            _ if arg.starts_with('-') => return Err(invalid(format!("Unknown option '{}'.", arg))),
+            _ if command.is_none() => command = Some(arg),
+            _ => operands.push(arg),
+        }
+    }
+
+    if matches!(parsed.command, Command::Help | Command::Version) {
+        return Ok(parsed);
+    }
+
+    if parsed
+        .speed
+        .is_some_and(|speed| speed <= 0.0 || !speed.is_finite())
+    {
+        return Err(invalid("--speed has to be above 0."));
+    }
+
+    let mut operands = operands.into_iter();
+
+    parsed.command = match command.as_deref() {
+        None => Command::Menu,
+        Some("play") => Command::Play,
+        Some("host") => Command::Host,
+        Some("join") => {
+            let code = operands
+                .next()
+                .ok_or_else(|| invalid("join needs a session code."))?;
+            // Catch typos before connecting to anything
+            Filter::parse_session_code(&code)?;
+            Command::Join(code)
+        }
+        Some("stats") => Command::Stats,
+        Some("serve") => {
+            Command::Serve(operands.next().unwrap_or_else(|| "0.0.0.0:8080".to_owned()))
+        }
+        // Older versions took the server address as the only argument
+        Some(addr) if addr.contains(':') => {
+            parsed.server = Some(addr.to_owned());
+            Command::Menu
+        }
+        Some(command) => return Err(invalid(format!("Unknown command '{}'.", command))),
+    };
+
+    if let Some(operand) = operands.next() {
+        return Err(invalid(format!("Unexpected argument '{}'.", operand)));
+    }
+
+    if let Some(server) = &parsed.server {
+        server
+            .to_socket_addrs()
+            .map_err(|err| invalid(format!("Invalid server address '{}': {}", server, err)))?;
+    }
+
+    Ok(parsed)
+}
+
+/// Prints the high scores and the most recent runs for the stats command.
+pub fn print_stats() -> io::Result<()> {
+    let print_runs = |runs: &[storage::Run]| {
+        println!("{}", storage::Run::header());
+        for (i, run) in runs.iter().enumerate() {
+            println!("{}", run.row(i + 1));
+        }
+    };
+
+    let history = storage::history()?;
+
+    if history.is_empty() {
+        println!("No runs recorded yet.");
+        return Ok(());
+    }
+
+    println!("High Scores");
+    print_runs(&storage::high_scores(10)?);
+
+    println!("\nRecent Runs");
+    let recent: Vec<_> = history.into_iter().rev().take(10).collect();
+    print_runs(&recent);
+
+    Ok(())
+}
@@ -0,0 +1,490 @@
+use super::dictionary::Filter;
+use crossterm::{event::KeyCode, style::Color};
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, Error, ErrorKind},
+    net::ToSocketAddrs,
+    path::PathBuf,
+    str::FromStr,
+};
+
+/// Keys for everything that isn't typing. Only non-character keys can be bound,
+/// otherwise they'd get in the way of typing the words.
+#[derive(Clone, Copy)]
+pub struct KeyBindings {
+    pub single_player: KeyCode,
+    pub host: KeyCode,
+    pub join: KeyCode,
+    pub high_scores: KeyCode,
+    // Plays the seed of the last Single Player run again
+    pub replay: KeyCode,
+    pub back: KeyCode,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            single_player: KeyCode::F(1),
+            host: KeyCode::F(2),
+            join: KeyCode::F(3),
+            high_scores: KeyCode::F(4),
+            replay: KeyCode::F(5),
+            back: KeyCode::Esc,
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Every binding along with its name in the config file.
+    fn named(&self) -> [(&'static str, KeyCode); 6] {
+        [
+            ("single_player", self.single_player),
+            ("host", self.host),
+            ("join", self.join),
+            ("high_scores", self.high_scores),
+            ("replay", self.replay),
+            ("back", self.back),
+        ]
+    }
+}
+
+/// Display name of a key as used in the menus and the config file.
+pub fn key_name(key: KeyCode) -> String {
+    match key {
+        KeyCode::F(n) => format!("F{}", n),
+        KeyCode::Esc => "ESC".to_owned(),
+        KeyCode::Tab => "Tab".to_owned(),
+        KeyCode::Insert => "Insert".to_owned(),
+        KeyCode::Delete => "Delete".to_owned(),
+        KeyCode::Home => "Home".to_owned(),
+        KeyCode::End => "End".to_owned(),
+        KeyCode::PageUp => "PageUp".to_owned(),
+        KeyCode::PageDown => "PageDown".to_owned(),
+        KeyCode::Up => "Up".to_owned(),
+        KeyCode::Down => "Down".to_owned(),
+        KeyCode::Left => "Left".to_owned(),
+        KeyCode::Right => "Right".to_owned(),
+        _ => "?".to_owned(),
+    }
+}
+
+fn parse_key(s: &str) -> Option<KeyCode> {
+    let key = match s.to_lowercase().as_str() {
+        "esc" | "escape" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "insert" => KeyCode::Insert,
+        "delete" => KeyCode::Delete,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        key => match key.strip_prefix('f')?.parse() {
+            Ok(n) if (1..=12).contains(&n) => KeyCode::F(n),
+            _ => return None,
+        },
+    };
+
+    Some(key)
+}
+
+#[derive(Clone)]
+pub struct Theme {
+    pub correct: Color,
+    pub incorrect: Color,
+    pub text: Color,
+    // One color per player lane in multiplayer, the last one is reused for everyone after
+    pub players: Vec<Color>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            correct: Color::Green,
+            incorrect: Color::Red,
+            text: Color::White,
+            players: vec![
+                Color::Blue,
+                Color::Red,
+                Color::Green,
+                Color::Yellow,
+                Color::White,
+            ],
+        }
+    }
+}
+
+impl Theme {
+    pub fn player(&self, i: usize) -> Color {
+        self.players
+            .get(i)
+            .or_else(|| self.players.last())
+            .copied()
+            .unwrap_or(Color::White)
+    }
+}
+
+/// Names crossterm knows (red, dark_blue, grey, ...) or #rrggbb.
+fn parse_color(s: &str) -> Option<Color> {
+    if let Some(hex) = s.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return Some(Color::Rgb {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+        });
+    }
+
+    Color::try_from(s).ok()
+}
+
+pub struct Config {
+    pub server: String,
+    pub nickname: Option<String>,
+    pub speed: f32,
+    pub adaptive: bool,
+    pub dict: Option<String>,
+    pub filter: Filter,
+    pub keys: KeyBindings,
+    pub theme: Theme,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            server: "127.0.0.1:8080".to_owned(),
+            nickname: None,
+            speed: 1.0,
+            adaptive: false,
+            dict: None,
+            filter: Filter::default(),
+            keys: KeyBindings::default(),
+            theme: Theme::default(),
+        }
+    }
+}
+
+/// $TIP_CONFIG, otherwise $XDG_CONFIG_HOME/tip/config.toml falling back to
+/// ~/.config/tip/config.toml (%APPDATA%\tip\config.toml on Windows).
+pub fn path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("TIP_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+
+    let dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+
+    dir.map(|dir| dir.join("tip").join("config.toml"))
+}
+
+/// Reads the config file if there is one and applies the TIP_* environment variables on top.
+pub fn load() -> io::Result<Config> {
+    let mut config = match path() {
+        Some(path) => match fs::read_to_string(&path) {
+            Ok(contents) => parse(&contents)
+                .map_err(|err| Error::new(err.kind(), format!("{}: {}", path.display(), err)))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Config::default(),
+            Err(err) => {
+                return Err(Error::new(
+                    err.kind(),
+                    format!("Couldn't read {}: {}", path.display(), err),
+                ))
+            }
+        },
+        None => Config::default(),
+    };
+
+    for (var, key) in [
+        ("TIP_SERVER", "server"),
+        ("TIP_NICKNAME", "nickname"),
+        ("TIP_SPEED", "speed"),
+        ("TIP_DICT", "dict"),
+    ] {
+        if let Ok(value) = env::var(var) {
+            config
+                .set("", key, &value)
+                .map_err(|err| Error::new(err.kind(), format!("{}: {}", var, err)))?;
+        }
+    }
+
+    Ok(config)
+}
+
+/// A small subset of TOML: `[section]` headers, `key = value` pairs with optionally quoted
+/// values, comma separated lists and # comments.
+pub fn parse(contents: &str) -> io::Result<Config> {
+    let mut config = Config::default();
+
+    let mut section = String::new();
+
+    // Line each key binding was set on, to point at the one that clashes
+    let mut bound = HashMap::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = strip_comment(line).trim();
+
+        let line_err = |err: Error| Error::new(err.kind(), format!("line {}: {}", i + 1, err));
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            section = name.trim().to_owned();
+            if !matches!(section.as_str(), "keys" | "theme") {
+                return Err(line_err(invalid(format!("unknown section [{}]", section))));
+            }
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| line_err(invalid("expected key = value")))?;
+
+        config
+            .set(&section, key.trim(), value.trim())
+            .map_err(line_err)?;
+
+        if section == "keys" {
+            bound.insert(key.trim(), i + 1);
+        }
+    }
+
+    // Only one of the actions would ever be reachable
+    let named = config.keys.named();
+
+    for (i, &(first, key)) in named.iter().enumerate() {
+        if let Some(&(second, _)) = named[i + 1..].iter().find(|(_, other)| *other == key) {
+            let line = bound
+                .get(first)
+                .max(bound.get(second))
+                .copied()
+                .unwrap_or(0);
+
+            return Err(invalid(format!(
+                "line {}: {} and {} are both bound to {}",
+                line,
+                first,
+                second,
+                key_name(key)
+            )));
+        }
+    }
+
+    Ok(config)
+}
+
+/// Cuts the line off at the first # that isn't inside quotes, "#rrggbb" colors contain one.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => (),
+        }
+    }
+
+    line
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> io::Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("invalid value '{}' for {}", value, key)))
+}
+
+impl Config {
+    fn set(&mut self, section: &str, key: &str, value: &str) -> io::Result<()> {
+        let value = if key == "players" {
+            value
+        } else {
+            unquote(value)
+        };
+
+        match (section, key) {
+            ("", "server") => {
+                value.to_socket_addrs().map_err(|err| {
+                    invalid(format!("invalid server address '{}': {}", value, err))
+                })?;
+                self.server = value.to_owned();
+            }
+            ("", "nickname") => self.nickname = Some(value.to_owned()),
+            ("", "speed") => {
+                self.speed = parse_value(key, value)?;
+                if self.speed <= 0.0 || !self.speed.is_finite() {
+                    return Err(invalid("speed has to be above 0"));
+                }
+            }
+            ("", "adaptive") => self.adaptive = parse_value(key, value)?,
+            ("", "dict") => self.dict = Some(value.to_owned()),
+            ("", "min_length") => self.filter.min_length = Some(parse_value(key, value)?),
+            ("", "max_length") => self.filter.max_length = Some(parse_value(key, value)?),
+            ("", "chars") => self.filter.chars = Some(value.to_owned()),
+            ("", "difficulty") => self.filter.difficulty = Some(value.parse()?),
+            ("", "skew") => self.filter.skew = Some(parse_value(key, value)?),
+            ("", "punctuation") => self.filter.punctuation = Some(parse_value(key, value)?),
+            ("keys", _) => {
+                let target = match key {
+                    "single_player" => &mut self.keys.single_player,
+                    "host" => &mut self.keys.host,
+                    "join" => &mut self.keys.join,
+                    "high_scores" => &mut self.keys.high_scores,
+                    "replay" => &mut self.keys.replay,
+                    "back" => &mut self.keys.back,
+                    _ => return Err(invalid(format!("unknown key binding '{}'", key))),
+                };
+                *target = parse_key(value).ok_or_else(|| {
+                    invalid(format!("'{}' can't be used as a key binding", value))
+                })?;
+            }
+            ("theme", "players") => {
+                self.theme.players = value
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .split(',')
+                    .map(|color| {
+                        let color = unquote(color.trim());
+                        parse_color(color)
+                            .ok_or_else(|| invalid(format!("unknown color '{}'", color)))
+                    })
+                    .collect::<io::Result<_>>()?;
+            }
+            ("theme", _) => {
+                let target = match key {
+                    "correct" => &mut self.theme.correct,
+                    "incorrect" => &mut self.theme.incorrect,
+                    "text" => &mut self.theme.text,
+                    _ => return Err(invalid(format!("unknown theme color '{}'", key))),
+                };
+                *target = parse_color(value)
+                    .ok_or_else(|| invalid(format!("unknown color '{}'", value)))?;
+            }
+            _ => return Err(invalid(format!("unknown setting '{}'", key))),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::Difficulty;
+
+    #[test]
+    fn parse_everything() {
+        let config = parse(
+            r##"
+# A comment
+server = "127.0.0.1:9000"
+nickname = "selyatin" # Another one
+speed = 1.25
+adaptive = true
+min_length = 3
+max_length = 10
+chars = "asdf#jkl"
+difficulty = "medium"
+skew = 1
+punctuation = true
+
+[keys]
+host = "F6"
+back = "Tab"
+
+[theme]
+correct = "#00ff00"
+players = ["blue", "red", "#ff8800"]
+"##,
+        )
+        .unwrap();
+
+        assert_eq!(config.server, "127.0.0.1:9000");
+        assert_eq!(config.nickname.as_deref(), Some("selyatin"));
+        assert_eq!(config.speed, 1.25);
+        assert!(config.adaptive);
+        assert_eq!(config.filter.min_length, Some(3));
+        assert_eq!(config.filter.max_length, Some(10));
+        assert_eq!(config.filter.chars.as_deref(), Some("asdf#jkl"));
+        assert!(config.filter.difficulty == Some(Difficulty::Medium));
+        assert_eq!(config.filter.skew, Some(1.0));
+        assert_eq!(config.filter.punctuation, Some(true));
+        assert_eq!(config.keys.host, KeyCode::F(6));
+        assert_eq!(config.keys.back, KeyCode::Tab);
+        assert_eq!(config.keys.single_player, KeyCode::F(1));
+        assert_eq!(config.theme.correct, Color::Rgb { r: 0, g: 255, b: 0 });
+        assert_eq!(
+            config.theme.players,
+            [
+                Color::Blue,
+                Color::Red,
+                Color::Rgb {
+                    r: 255,
+                    g: 136,
+                    b: 0
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_is_default() {
+        let config = parse("\n# Nothing here\n").unwrap();
+
+        assert_eq!(config.server, Config::default().server);
+        assert_eq!(config.speed, 1.0);
+        assert!(config.filter == Filter::default());
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        for (contents, line) in [
+            ("speed = 0", 1),
+            ("\nspeed = fast", 2),
+            ("[keys]\nhost = \"a\"", 2),
+            ("[sounds]", 1),
+            ("\n\nunknown = 1", 3),
+            ("[theme]\ntext = \"nope\"", 2),
+            ("no equals sign", 1),
+            ("difficulty = \"insane\"", 1),
+            ("[keys]\nhost = \"F1\"", 2),
+            ("[keys]\nback = \"Tab\"\n\nhost = \"tab\"", 4),
+        ] {
+            let err = parse(contents).err().unwrap();
+            assert!(
+                err.to_string().starts_with(&format!("line {}:", line)),
+                "{}: {}",
+                contents,
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use super::types::Word;
+use fastrand::Rng;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    io::{self, Error, ErrorKind, Read},
+    str::FromStr,
+};
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The game always shows 4 words at once, so a word list needs at least that many.
+const MIN_WORDS: usize = 4;
+
+lazy_static! {
+    static ref EMBEDDED: Vec<Word> =
+        parse(include_str!("../dictionary.txt")).expect("The embedded dictionary is valid");
+}
+
+lazy_static! {
+    // Rank of the most common English words, starting at 1
+    static ref FREQUENCIES: HashMap<&'static str, usize> = include_str!("../frequency.txt")
+        .lines()
+        .map(str::trim)
+        .filter(|word| !word.is_empty())
+        .enumerate()
+        .map(|(i, word)| (word, i + 1))
+        .collect();
+}
+
+/// The compiled in English word list.
+pub fn embedded() -> Vec<Word> {
+    EMBEDDED.clone()
+}
+
+/// Loads a word list with one word per line from a file, or from stdin if the path is "-".
+pub fn load(path: &str) -> io::Result<Vec<Word>> {
+    let contents = if path == "-" {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        contents
+    } else {
+        fs::read_to_string(path)
+            .map_err(|err| Error::new(err.kind(), format!("Couldn't read {}: {}", path, err)))?
+    };
+
+    parse(&contents)
+}
+
+/// Empty lines and duplicates are skipped, words with whitespace or other
+/// non-printable characters make the whole list invalid.
+pub fn parse(contents: &str) -> io::Result<Vec<Word>> {
+    let mut seen = HashSet::new();
+    let mut words = vec![];
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.chars().any(|c| c.is_control() || c.is_whitespace()) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Line {} contains non-printable characters.", i + 1),
+            ));
+        }
+
+        // Terminals send precomposed characters, "é" typed has to match "é" in the list
+        let word: String = line.nfc().collect();
+
+        if seen.insert(word.clone()) {
+            words.push(Word::new(word, 0, 0));
+        }
+    }
+
+    if words.len() < MIN_WORDS {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("A word list needs at least {} words.", MIN_WORDS),
+        ));
+    }
+
+    Ok(words)
+}
+
+/// Identifies a word list by its words and their order, everyone in a multiplayer session has
+/// to play with the same one. FNV-1a, so it's the same for every build of tip.
+pub fn checksum(words: &[Word]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    for word in words {
+        // The separator keeps "ab", "c" apart from "a", "bc"
+        for &byte in word.value.as_bytes().iter().chain(b"\n") {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    hash
+}
+
+/// Shuffles the words, the higher the skew the more likely common words come first.
+/// Weights follow Zipf's law over the ranks in frequency.txt, so a skew of 1 roughly
+/// matches how often words show up in real text and a skew of 0 is a uniform shuffle.
+pub fn shuffle(words: &mut Vec<Word>, rng: &Rng, skew: f32) {
+    if skew <= 0.0 {
+        rng.shuffle(words);
+        return;
+    }
+
+    let skew = f64::from(skew);
+
+    let known = FREQUENCIES.len();
+
+    let unknown = words
+        .iter()
+        .filter(|word| !FREQUENCIES.contains_key(word.value.as_str()))
+        .count();
+
+    // Words that aren't in the frequency list evenly share the weight of the ranks after it,
+    // instead of getting ranked by wherever they happen to be in the word list
+    let unknown_weight = (known + 1..=known + unknown)
+        .map(|rank| (rank as f64).powf(-skew))
+        .sum::<f64>()
+        / unknown.max(1) as f64;
+
+    // Weighted sampling without replacement (Efraimidis-Spirakis), the smallest key comes first
+    let mut keyed: Vec<(f64, Word)> = words
+        .drain(..)
+        .map(|word| {
+            let weight = FREQUENCIES
+                .get(word.value.as_str())
+                .map_or(unknown_weight, |&rank| (rank as f64).powf(-skew));
+            (-(1.0 - rng.f64()).ln() / weight, word)
+        })
+        .collect();
+
+    keyed.sort_by(|(key_a, _), (key_b, _)| key_a.total_cmp(key_b));
+
+    words.extend(keyed.into_iter().map(|(_, word)| word));
+}
+
+/// Turns the words into something closer to real text. Sentences start with a capital letter
+/// and end with a full stop, a question or an exclamation mark, with the odd comma, colon or
+/// quote in between.
+pub fn punctuate(words: &mut [Word], rng: &Rng) {
+    let mut sentence_start = true;
+
+    for word in words {
+        if sentence_start {
+            let mut chars = word.value.chars();
+            if let Some(first) = chars.next() {
+                word.value = first.to_uppercase().chain(chars).collect();
+            }
+        }
+
+        sentence_start = false;
+
+        match rng.u8(0..24) {
+            0..=2 => {
+                word.value.push('.');
+                sentence_start = true;
+            }
+            3 => {
+                word.value.push('?');
+                sentence_start = true;
+            }
+            4 => {
+                word.value.push('!');
+                sentence_start = true;
+            }
+            5..=7 => word.value.push(','),
+            8 => word.value.push(';'),
+            9 => word.value.push(':'),
+            10 => word.value = format!("\"{}\"", word.value),
+            11 => word.value = format!("({})", word.value),
+            _ => (),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    /// Longer words and words with rare letters are harder to type.
+    pub fn of(word: &str) -> Self {
+        let score: f32 = word
+            .chars()
+            .map(|c| match c.to_ascii_lowercase() {
+                'e' | 't' | 'a' | 'o' | 'i' | 'n' | 's' | 'h' | 'r' => 1.0,
+                'd' | 'l' | 'c' | 'u' | 'm' | 'w' | 'f' | 'g' | 'y' | 'p' | 'b' => 1.5,
+                _ => 3.0,
+            })
+            .sum();
+
+        if score <= 7.0 {
+            Difficulty::Easy
+        } else if score <= 13.0 {
+            Difficulty::Medium
+        } else {
+            Difficulty::Hard
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+        }
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "easy" => Ok(Difficulty::Easy),
+            "medium" => Ok(Difficulty::Medium),
+            "hard" => Ok(Difficulty::Hard),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown difficulty {}, expected easy, medium or hard.", s),
+            )),
+        }
+    }
+}
+
+/// Decides which words show up in a run, how often and how they're written. In multiplayer
+/// every client has to use the same Filter, so it travels along with the session token in the
+/// session code.
+#[derive(Clone, Default, PartialEq)]
+pub struct Filter {
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    // Only words made up entirely of these characters are kept
+    pub chars: Option<String>,
+    pub difficulty: Option<Difficulty>,
+    // How strongly common words are preferred, see shuffle
+    pub skew: Option<f32>,
+    // Capital letters and punctuation like in real text, see punctuate
+    pub punctuation: Option<bool>,
+}
+
+impl Filter {
+    pub fn matches(&self, word: &str) -> bool {
+        let length = word.graphemes(true).count();
+
+        self.min_length.is_none_or(|min| length >= min)
+            && self.max_length.is_none_or(|max| length <= max)
+            && self
+                .chars
+                .as_ref()
+                .is_none_or(|chars| word.chars().all(|c| chars.contains(c)))
+            && self
+                .difficulty
+                .is_none_or(|difficulty| Difficulty::of(word) == difficulty)
+    }
+
+    /// Fills every part that isn't set from the fallback Filter.
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            min_length: self.min_length.or(fallback.min_length),
+            max_length: self.max_length.or(fallback.max_length),
+            chars: self.chars.or(fallback.chars),
+            difficulty: self.difficulty.or(fallback.difficulty),
+            skew: self.skew.or(fallback.skew),
+            punctuation: self.punctuation.or(fallback.punctuation),
+        }
+    }
+
+    pub fn apply(&self, word_list: &[Word]) -> io::Result<Vec<Word>> {
+        let words: Vec<Word> = word_list
+            .iter()
+            .filter(|word| self.matches(&word.value))
+            .cloned()
+            .collect();
+
+        if words.len() < MIN_WORDS {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Not enough words match the word filter.",
+            ));
+        }
+
+        Ok(words)
+    }
+
+    /// The session code players need to join a session using this Filter,
+    /// just the token when nothing is filtered so it stays compatible with tip-server codes.
+    pub fn session_code(&self, session_token: u16) -> String {
+        if *self == Self::default() {
+            session_token.to_string()
+        } else {
+            format!("{}:{}", session_token, self)
+        }
+    }
+
+    /// Splits a session code into the session token and the session's Filter.
+    pub fn parse_session_code(code: &str) -> io::Result<(u16, Self)> {
+        let (token, filter) = code.split_once(':').unwrap_or((code, ""));
+
+        let token = token.trim().parse().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid session code '{}'.", code),
+            )
+        })?;
+
+        Ok((token, filter.parse()?))
+    }
+}
+
+/// Colon separated parts, e.g. "3-8:hard:~1:!:=asdfjkl" for 3 to 8 letter hard words typed
+/// with the home row only, common ones first, with capitals and punctuation. The characters
+/// come last and take up the rest of the code, so they can be anything, colons included.
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+
+        if self.min_length.is_some() || self.max_length.is_some() {
+            let length = |length: Option<usize>| length.map(|l| l.to_string()).unwrap_or_default();
+            parts.push(format!(
+                "{}-{}",
+                length(self.min_length),
+                length(self.max_length)
+            ));
+        }
+
+        if let Some(difficulty) = self.difficulty {
+            parts.push(difficulty.as_str().to_owned());
+        }
+
+        if let Some(skew) = self.skew {
+            parts.push(format!("~{}", skew));
+        }
+
+        if self.punctuation == Some(true) {
+            parts.push("!".to_owned());
+        }
+
+        if let Some(chars) = &self.chars {
+            parts.push(format!("={}", chars));
+        }
+
+        write!(f, "{}", parts.join(":"))
+    }
+}
+
+impl FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let mut filter = Self::default();
+
+        let invalid_length = || Error::new(ErrorKind::InvalidInput, "Invalid word length.");
+
+        let mut rest = s;
+
+        while !rest.is_empty() {
+            if let Some(chars) = rest.strip_prefix('=') {
+                filter.chars = Some(chars.to_owned());
+                break;
+            }
+
+            let (part, next) = rest.split_once(':').unwrap_or((rest, ""));
+            rest = next;
+
+            if part.is_empty() {
+                continue;
+            }
+
+            // Before the length, a negative skew has a '-' in it too
+            if let Some(skew) = part.strip_prefix('~') {
+                filter.skew = Some(
+                    skew.parse()
+                        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid skew."))?,
+                );
+            } else if let Some((min, max)) = part.split_once('-') {
+                if !min.is_empty() {
+                    filter.min_length = Some(min.parse().map_err(|_| invalid_length())?);
+                }
+                if !max.is_empty() {
+                    filter.max_length = Some(max.parse().map_err(|_| invalid_length())?);
+                }
+            } else if part == "!" {
+                filter.punctuation = Some(true);
+            } else {
+                filter.difficulty = Some(part.parse()?);
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_code_round_trip() {
+        let filters = [
+            Filter::default(),
+            Filter {
+                min_length: Some(3),
+                max_length: Some(8),
+                difficulty: Some(Difficulty::Hard),
+                skew: Some(1.0),
+                punctuation: Some(true),
+                chars: Some("asdfjkl".to_owned()),
+            },
+            Filter {
+                max_length: Some(5),
+                ..Default::default()
+            },
+            // Characters that look like the other parts
+            Filter {
+                chars: Some("a-z".to_owned()),
+                ..Default::default()
+            },
+            Filter {
+                chars: Some("hard".to_owned()),
+                ..Default::default()
+            },
+            Filter {
+                chars: Some("~!:=a".to_owned()),
+                ..Default::default()
+            },
+            Filter {
+                skew: Some(-1.0),
+                ..Default::default()
+            },
+            Filter {
+                skew: Some(0.25),
+                chars: Some("éß".to_owned()),
+                ..Default::default()
+            },
+        ];
+
+        for filter in filters {
+            let code = filter.session_code(4242);
+            let (session_token, parsed) = Filter::parse_session_code(&code).unwrap();
+            assert_eq!(session_token, 4242);
+            assert!(parsed == filter, "{} didn't round trip", code);
+        }
+    }
+
+    #[test]
+    fn checksum_tells_word_lists_apart() {
+        let words = |words: &[&str]| -> Vec<Word> {
+            words.iter().map(|word| Word::new(*word, 0, 0)).collect()
+        };
+
+        let checksum_of = |list: &[&str]| checksum(&words(list));
+
+        assert_eq!(checksum_of(&["a", "bc"]), checksum_of(&["a", "bc"]));
+        assert_ne!(checksum_of(&["a", "bc"]), checksum_of(&["ab", "c"]));
+        assert_ne!(checksum_of(&["a", "bc"]), checksum_of(&["bc", "a"]));
+        assert_ne!(checksum_of(&["a", "bc"]), checksum_of(&["a", "bc", "d"]));
+    }
+
+    #[test]
+    fn invalid_session_codes() {
+        for code in ["", "abc", "4242:3-x", "4242:~x", "4242:asdf", "99999"] {
+            assert!(
+                Filter::parse_session_code(code).is_err(),
+                "{} was accepted",
+                code
+            );
+        }
+    }
+}
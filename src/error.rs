@@ -0,0 +1,71 @@
+use std::{fmt, io};
+
+/// Everything that can go wrong while playing, by where it went wrong
+#[derive(Debug)]
+pub enum Error {
+    // Couldn't reach the session server or lost the connection to it
+    Network(io::Error),
+    // Lost the connection in the middle of a session and couldn't get back in
+    Disconnected(io::Error),
+    // The session server refused or answered with something unexpected
+    Protocol(io::Error),
+    // The session server speaks a different protocol version
+    Mismatch(io::Error),
+    // The word list or the word filter can't be used
+    Dictionary(io::Error),
+    // The config file or one of the TIP_* environment variables is invalid
+    Config(io::Error),
+    // The run history couldn't be read or written
+    Storage(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn title(&self) -> &'static str {
+        match self {
+            Error::Network(_) => "Network Error",
+            Error::Disconnected(_) => "Connection Lost",
+            Error::Protocol(_) => "Session Error",
+            Error::Mismatch(_) => "Version Mismatch",
+            Error::Dictionary(_) => "Dictionary Error",
+            Error::Config(_) => "Invalid Config",
+            Error::Storage(_) => "Storage Error",
+        }
+    }
+
+    /// What the player can do about it.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            Error::Network(_) => "Check that the session server is running and can be reached.",
+            Error::Disconnected(_) => "Check your network, then host or join a new session.",
+            Error::Protocol(_) => {
+                "Check the session code, the session might be over or the server might run another version of tip."
+            }
+            Error::Mismatch(_) => "Update whichever is older, the client or the server.",
+            Error::Dictionary(_) => "Check the word list and the word filters.",
+            Error::Config(_) => "The default settings are used until it's fixed.",
+            Error::Storage(_) => "Check that tip's data directory can be written to.",
+        }
+    }
+
+    fn inner(&self) -> &io::Error {
+        match self {
+            Error::Network(err)
+            | Error::Disconnected(err)
+            | Error::Protocol(err)
+            | Error::Mismatch(err)
+            | Error::Dictionary(err)
+            | Error::Config(err)
+            | Error::Storage(err) => err,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.inner())
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,207 @@
+use crossterm::{
+    cursor::MoveTo,
+    queue,
+    style::{
+        Attribute, ContentStyle, Print, PrintStyledContent, SetAttribute, SetAttributes,
+        SetBackgroundColor, SetForegroundColor,
+    },
+    terminal::{Clear, ClearType},
+};
+use std::{
+    fmt::Display,
+    io::{self, Write},
+};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Clone, PartialEq)]
+struct Cell {
+    // A whole grapheme, empty when the wide one to the left of it covers this cell too
+    symbol: String,
+    style: ContentStyle,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            symbol: " ".to_owned(),
+            style: ContentStyle::default(),
+        }
+    }
+}
+
+impl Cell {
+    fn is_covered(&self) -> bool {
+        self.symbol.is_empty()
+    }
+}
+
+/// Off-screen copy of the terminal. Screens draw into it every frame, and rendering only
+/// sends the cells that changed since the previous frame so nothing flickers.
+pub struct Frame {
+    columns: u16,
+    rows: u16,
+    cells: Vec<Cell>,
+    // What the terminal currently shows, None when it has to be redrawn from scratch
+    previous: Option<Vec<Cell>>,
+    cursor: (u16, u16),
+}
+
+impl Frame {
+    pub fn new(columns: u16, rows: u16) -> Self {
+        Self {
+            columns,
+            rows,
+            cells: vec![Cell::default(); columns as usize * rows as usize],
+            previous: None,
+            cursor: (0, 0),
+        }
+    }
+
+    pub fn resize(&mut self, columns: u16, rows: u16) {
+        *self = Self::new(columns, rows);
+    }
+
+    /// Starts a new frame, everything that isn't drawn again gets erased on the next render.
+    pub fn clear(&mut self) {
+        self.cells.fill(Cell::default());
+        self.cursor = (0, 0);
+    }
+
+    /// Like the terminal's cursor, anything below the last row ends up on it.
+    pub fn move_to(&mut self, x: u16, y: u16) {
+        self.cursor = (x, y.min(self.rows.saturating_sub(1)));
+    }
+
+    /// Prints at the cursor and moves it along, whatever goes past the last column is cut off.
+    /// Wide characters like CJK take up two columns and combining marks stay with the character
+    /// they belong to.
+    pub fn print(&mut self, content: impl Display, style: ContentStyle) {
+        for grapheme in content.to_string().graphemes(true) {
+            if grapheme.chars().any(char::is_control) {
+                continue;
+            }
+
+            // Terminals don't draw anything wider than two columns for a single grapheme
+            let width = grapheme.width().min(2) as u16;
+
+            if width == 0 {
+                continue;
+            }
+
+            let (x, y) = self.cursor;
+
+            if x.saturating_add(width) <= self.columns && y < self.rows {
+                self.put(x, y, grapheme.to_owned(), style);
+                if width == 2 {
+                    self.put(x + 1, y, String::new(), style);
+                }
+            }
+
+            self.cursor.0 = x.saturating_add(width);
+        }
+    }
+
+    /// Overwrites a single cell, and blanks the other half of any wide character it hits.
+    fn put(&mut self, x: u16, y: u16, symbol: String, style: ContentStyle) {
+        let i = y as usize * self.columns as usize + x as usize;
+
+        if self.cells[i].is_covered() && !symbol.is_empty() && x > 0 {
+            self.cells[i - 1] = Cell::default();
+        }
+
+        if x + 1 < self.columns && self.cells[i + 1].is_covered() {
+            self.cells[i + 1] = Cell::default();
+        }
+
+        self.cells[i] = Cell { symbol, style };
+    }
+
+    /// Sends the cells that changed since the last render to the terminal.
+    pub fn render(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let previous = match self.previous.take() {
+            Some(previous) => previous,
+            None => {
+                queue!(out, SetAttribute(Attribute::Reset), Clear(ClearType::All))?;
+                vec![Cell::default(); self.cells.len()]
+            }
+        };
+
+        let columns = self.columns.max(1) as usize;
+
+        let mut cursor = None;
+        let mut style = None;
+
+        for (i, (cell, previous)) in self.cells.iter().zip(&previous).enumerate() {
+            // The wide character to the left already took care of it
+            if cell == previous || cell.is_covered() {
+                continue;
+            }
+
+            let (x, y) = ((i % columns) as u16, (i / columns) as u16);
+
+            if cursor != Some((x, y)) {
+                queue!(out, MoveTo(x, y))?;
+            }
+
+            if style != Some(cell.style) {
+                queue!(out, SetAttribute(Attribute::Reset))?;
+                if let Some(color) = cell.style.foreground_color {
+                    queue!(out, SetForegroundColor(color))?;
+                }
+                if let Some(color) = cell.style.background_color {
+                    queue!(out, SetBackgroundColor(color))?;
+                }
+                queue!(out, SetAttributes(cell.style.attributes))?;
+                style = Some(cell.style);
+            }
+
+            queue!(out, Print(&cell.symbol))?;
+
+            cursor = Some((x + cell.symbol.width().clamp(1, 2) as u16, y));
+        }
+
+        // Something was drawn, don't leave its style behind
+        if style.is_some() {
+            queue!(out, SetAttribute(Attribute::Reset))?;
+        }
+
+        out.flush()?;
+
+        self.previous = Some(self.cells.clone());
+
+        Ok(())
+    }
+}
+
+/// Crossterm commands that can be drawn into a Frame instead of being sent to the terminal
+pub trait Draw {
+    fn draw(self, frame: &mut Frame);
+}
+
+impl Draw for MoveTo {
+    fn draw(self, frame: &mut Frame) {
+        frame.move_to(self.0, self.1);
+    }
+}
+
+impl<T: Display> Draw for Print<T> {
+    fn draw(self, frame: &mut Frame) {
+        frame.print(self.0, ContentStyle::default());
+    }
+}
+
+impl<D: Display> Draw for PrintStyledContent<D> {
+    fn draw(self, frame: &mut Frame) {
+        frame.print(self.0.content(), *self.0.style());
+    }
+}
+
+/// Like crossterm's queue!, but draws into a Frame.
+macro_rules! draw {
+    ($frame:expr $(, $command:expr)* $(,)?) => {{
+        $($crate::frame::Draw::draw($command, $frame);)*
+    }};
+}
+
+pub(crate) use draw;
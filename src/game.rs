@@ -0,0 +1,233 @@
+use super::types::{Action, Pace, Player, Screen, State, Word, VISIBLE_WORDS};
+use std::time::Duration;
+
+/// How much game time a single tick simulates, independent of how often frames are drawn
+pub const TICK: Duration = Duration::from_millis(10);
+
+/// Game time that can pile up between two frames, a stalled terminal skips ahead instead of
+/// making the words jump across the screen afterwards
+pub const MAX_LAG: Duration = Duration::from_millis(250);
+
+/// Advances the game by one TICK without touching the terminal or the network.
+/// Returns whether the current player's run is over.
+pub fn tick(state: &mut State) -> bool {
+    match state.screen {
+        Screen::SinglePlayer => single_player(state),
+        Screen::MultiPlayer => multi_player(state),
+        _ => false,
+    }
+}
+
+/// Applies an Action received from the session server.
+pub fn apply(state: &mut State, action: Action) {
+    match action {
+        Action::Join(position) => {
+            state.players.push(Player {
+                sort_position: position,
+                ..Default::default()
+            });
+            sort_players(state);
+        }
+        Action::Left(position) => {
+            if position < state.players.len() {
+                state.players.remove(position);
+            }
+            sort_players(state);
+        }
+        Action::Input((position, edit)) if state.screen == Screen::MultiPlayer => {
+            if let Some(player) = state.players.get_mut(position) {
+                player.edit(edit);
+            }
+        }
+        Action::Forward => match state.screen {
+            // The first tick after the lobby starts the game
+            Screen::Loading => {
+                state.players[state.current_player].stats.start();
+                state.screen = Screen::MultiPlayer;
+            }
+            Screen::MultiPlayer => {
+                for player in state.players.iter().filter(|player| player.is_alive()) {
+                    step_words(&mut state.dictionary, player.position, VISIBLE_WORDS);
+                }
+            }
+            _ => (),
+        },
+        _ => (),
+    }
+}
+
+fn single_player(state: &mut State) -> bool {
+    let player = &mut state.players[state.current_player];
+
+    state.pace = if state.adaptive {
+        Pace::adaptive(player, state.columns, state.speed)
+    } else {
+        Pace::of_level(player.level(), state.speed)
+    };
+
+    // Ran out of words, nothing left to play
+    if player.position + state.pace.visible_words > state.dictionary.len() {
+        return true;
+    }
+
+    check_front_word(player, &state.dictionary, state.columns);
+
+    state.since_step += TICK;
+
+    if state.since_step > state.pace.step_interval {
+        state.since_step = Duration::ZERO;
+        step_words(
+            &mut state.dictionary,
+            player.position,
+            state.pace.visible_words,
+        );
+    }
+
+    !player.is_alive()
+}
+
+fn multi_player(state: &mut State) -> bool {
+    for player in state.players.iter_mut().filter(|player| player.is_alive()) {
+        check_front_word(player, &state.dictionary, state.columns);
+
+        if !player.is_alive() {
+            player.stats.finish();
+        }
+    }
+
+    // Everyone's out of lives
+    !state.players.iter().any(Player::is_alive)
+}
+
+/// Moves the Player on once the front word is typed or has escaped the screen.
+fn check_front_word(player: &mut Player, dictionary: &[Word], columns: u16) {
+    let Some(word) = dictionary.get(player.position) else {
+        return;
+    };
+
+    if word.is_typed_by(&player.input) {
+        player.next_word(true);
+    } else if word.x >= columns {
+        player.next_word(false);
+    }
+}
+
+/// The first visible word moves the fastest, the last one a column per step.
+fn step_words(dictionary: &mut [Word], position: usize, visible_words: usize) {
+    let steps = (1..=visible_words as u16).rev();
+
+    for (word, add_x) in dictionary.iter_mut().skip(position).zip(steps) {
+        word.x = word.x.saturating_add(add_x);
+    }
+}
+
+fn sort_players(state: &mut State) {
+    state.players.sort_by_key(|player| player.sort_position);
+
+    if let Some(i) = state
+        .players
+        .iter()
+        .position(|player| player.current_player)
+    {
+        state.current_player = i;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cli, protocol::Edit, types::LIVES};
+
+    fn state(screen: Screen, players: usize) -> State {
+        let mut state = State::new(cli::parse(vec![]).unwrap(), 80, 24);
+        state.screen = screen;
+        state.dictionary = (0..20)
+            .map(|i| Word::new(format!("w{}", i), 0, 1))
+            .collect();
+        state.players = (0..players)
+            .map(|i| Player {
+                sort_position: i as u8,
+                current_player: i == 0,
+                ..Default::default()
+            })
+            .collect();
+        state
+    }
+
+    #[test]
+    fn escaped_word_costs_a_life() {
+        for screen in [Screen::SinglePlayer, Screen::MultiPlayer] {
+            let mut state = state(screen, 1);
+            state.dictionary[0].x = state.columns;
+
+            assert!(!tick(&mut state));
+
+            let player = &state.players[0];
+            assert_eq!(player.lives, LIVES - 1);
+            assert_eq!(player.position, 1);
+            assert_eq!(player.stats.missed_words, 1);
+        }
+    }
+
+    #[test]
+    fn typed_word_advances_position() {
+        for screen in [Screen::SinglePlayer, Screen::MultiPlayer] {
+            let mut state = state(screen, 1);
+            state.players[0].input = "w0".to_owned();
+
+            tick(&mut state);
+
+            let player = &state.players[0];
+            assert_eq!(player.lives, LIVES);
+            assert_eq!(player.position, 1);
+            assert_eq!(player.stats.completed_words, 1);
+            assert!(player.input.is_empty());
+        }
+    }
+
+    #[test]
+    fn last_life_ends_the_run() {
+        let mut state = state(Screen::SinglePlayer, 1);
+        state.players[0].lives = 1;
+        state.dictionary[0].x = state.columns;
+
+        assert!(tick(&mut state));
+    }
+
+    #[test]
+    fn forward_steps_only_alive_players_words() {
+        let mut state = state(Screen::MultiPlayer, 2);
+        state.players[1].position = 10;
+        state.players[1].lives = 0;
+
+        apply(&mut state, Action::Forward);
+
+        let x: Vec<u16> = state.dictionary.iter().map(|word| word.x).collect();
+        assert_eq!(x[..4], [4, 3, 2, 1]);
+        assert!(x[4..].iter().all(|&x| x == 0));
+    }
+
+    #[test]
+    fn forward_starts_the_game() {
+        let mut state = state(Screen::Loading, 2);
+
+        apply(&mut state, Action::Forward);
+
+        assert!(state.screen == Screen::MultiPlayer);
+        assert!(state.dictionary.iter().all(|word| word.x == 0));
+    }
+
+    #[test]
+    fn input_reaches_the_right_player() {
+        let mut state = state(Screen::MultiPlayer, 3);
+
+        apply(&mut state, Action::Input((1, Edit::Char('w'))));
+        apply(&mut state, Action::Input((1, Edit::Char('0'))));
+        apply(&mut state, Action::Input((2, Edit::Char('x'))));
+        apply(&mut state, Action::Input((2, Edit::Backspace)));
+
+        assert_eq!(state.players[0].input, "");
+        assert_eq!(state.players[1].input, "w0");
+        assert_eq!(state.players[2].input, "");
+    }
+}
@@ -0,0 +1,85 @@
+use crossterm::{
+    cursor::{Hide, Show},
+    execute,
+    style::ResetColor,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::{
+    io::{self, stdout},
+    panic,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+// Whether the terminal is set up for the game and still has to be restored
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Puts the terminal into raw mode on the alternate screen with a hidden cursor, and makes sure
+/// it's put back when the guard is dropped, on a panic and on SIGINT, SIGTERM and SIGHUP.
+pub struct TerminalGuard;
+
+impl TerminalGuard {
+    pub fn new() -> io::Result<Self> {
+        let default_hook = panic::take_hook();
+        let main_thread = thread::current().id();
+
+        // The panic message would end up on the alternate screen and vanish with it otherwise.
+        // Panics on other threads are caught when they're joined and the game keeps running, so
+        // the terminal has to stay the way it is.
+        panic::set_hook(Box::new(move |info| {
+            if thread::current().id() == main_thread {
+                restore();
+            }
+            default_hook(info);
+        }));
+
+        #[cfg(unix)]
+        handle_signals()?;
+
+        terminal::enable_raw_mode()?;
+
+        ACTIVE.store(true, Ordering::SeqCst);
+
+        execute!(stdout(), EnterAlternateScreen, Hide)?;
+
+        Ok(Self)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Leaves raw mode and the alternate screen and shows the cursor again, only the first call
+/// does anything.
+pub fn restore() {
+    if !ACTIVE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+
+    // Nothing left to report these to, the terminal is as restored as it's going to get
+    let _ = execute!(stdout(), ResetColor, LeaveAlternateScreen, Show);
+    let _ = terminal::disable_raw_mode();
+}
+
+#[cfg(unix)]
+fn handle_signals() -> io::Result<()> {
+    use signal_hook::{
+        consts::{SIGHUP, SIGINT, SIGTERM},
+        iterator::Signals,
+    };
+
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            restore();
+            // The usual exit code of a process killed by a signal
+            std::process::exit(128 + signal);
+        }
+    });
+
+    Ok(())
+}
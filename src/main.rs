@@ -1,228 +1,547 @@
 #[macro_use]
 extern crate lazy_static;
 
+mod cli;
+mod config;
+mod dictionary;
+mod error;
+mod frame;
+mod game;
+mod guard;
+mod protocol;
 mod screens;
+mod server;
 mod socket;
+mod storage;
 mod types;
 
+use cli::Command;
 use crossterm::{
-    cursor::{Hide, MoveTo, Show},
+    cursor::MoveTo,
     event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
-    execute, queue,
+    queue,
     style::{style, Print, PrintStyledContent, Stylize},
     terminal::{self, Clear, ClearType},
 };
-use socket::Socket;
+use dictionary::Filter;
+use error::Error;
+use frame::{draw, Frame};
+use guard::TerminalGuard;
+use protocol::Edit;
+use socket::{Reconnect, Socket};
 use std::{
     env,
     io::{self, stdout, Stdout, Write},
     time::{Duration, Instant},
 };
-use types::{Player, Screen, State, Word};
-
-lazy_static! {
-    static ref DICTIONARY: Vec<Word> = include_str!("../dictionary.txt")
-        .split("\n")
-        .map(|s| Word::new(s, 0, 0))
-        .collect();
-}
+use types::{Player, Screen, State, Task, Word};
 
 fn reset_state(state: &mut State) {
     state.socket = None;
-    state.err = None;
+    state.error = None;
+    state.retry = None;
+    state.reconnect = None;
     state.session_token = None;
+    state.session_filter = Filter::default();
     state.current_player = 0;
+    state.since_step = Duration::ZERO;
     state.players.clear();
-    let mut player = Player::default();
-    player.current_player = true;
-    state.players.push(player);
+    state.players.push(Player {
+        current_player: true,
+        ..Default::default()
+    });
 }
 
-fn main_loop(stdout: &mut Stdout, state: &mut State) -> io::Result<()> {
-    // Clear the previous frame
-    queue!(stdout, Clear(ClearType::All))?;
+/// Shuffles the dictionary, punctuates it if asked to and spreads the words across the rows.
+fn shuffle_dictionary(
+    state: &mut State,
+    rng: &fastrand::Rng,
+    skew: Option<f32>,
+    punctuation: Option<bool>,
+) {
+    dictionary::shuffle(&mut state.dictionary, rng, skew.unwrap_or(0.0));
+
+    if punctuation == Some(true) {
+        dictionary::punctuate(&mut state.dictionary, rng);
+    }
 
-    match state.screen {
-        Screen::Main => screens::main(stdout, state)?,
-        Screen::SinglePlayer => screens::single_player(stdout, state)?,
-        Screen::Join => screens::join(stdout, state)?,
-        Screen::MultiPlayer => screens::multi_player(stdout, state)?,
-        Screen::Loading => screens::loading(stdout, state)?,
-    };
+    let mut prev_y: u16 = 0;
 
-    if let Some(err) = &state.err {
-        queue!(
-            stdout,
-            MoveTo((state.columns as f32 * 0.4) as u16, state.rows),
-            PrintStyledContent("Error: ".red().bold()),
-            PrintStyledContent(style(err).red().bold())
-        )?;
+    for word in &mut state.dictionary {
+        let mut y = rng.u16(0..state.rows - 1);
+        if y == prev_y {
+            y += 2;
+            if y > state.rows - 1 {
+                y -= 3;
+            }
+        }
+        prev_y = y;
+        word.y = y;
     }
+}
 
-    if let Some(session_token) = &state.session_token {
-        queue!(
-            stdout,
-            MoveTo((state.columns as f32 * 0.1) as u16, state.rows),
-            PrintStyledContent("Session Token: ".green().bold()),
-            PrintStyledContent(style(session_token).green().bold())
-        )?;
-    }
+/// Starts a Single Player run with the given seed, or a random one.
+fn start_single_player(state: &mut State, seed: Option<u64>) -> error::Result<()> {
+    reset_state(state);
 
-    // Render the queued frame
-    stdout.flush()?;
+    state.dictionary = state
+        .filter
+        .apply(&state.word_list)
+        .map_err(Error::Dictionary)?;
 
-    if event::poll(Duration::from_millis(16))? {
-        match event::read()? {
-            Event::Key(KeyEvent {
-                code: KeyCode::Char(c),
-                modifiers: KeyModifiers::NONE,
-            }) => {
-                if let Some(player) = state.players.get_mut(state.current_player) {
-                    player.input.push(c);
-                    if let Some(socket) = &mut state.socket {
-                        socket.send_input(c)?;
-                    }
-                }
-            }
-            Event::Key(KeyEvent {
-                code: KeyCode::Enter,
-                modifiers: KeyModifiers::NONE,
-            }) => match state.screen {
-                Screen::Join => {
-                    let player = state.players.get_mut(state.current_player).unwrap();
+    let rng = match seed {
+        Some(seed) => fastrand::Rng::with_seed(seed),
+        None => fastrand::Rng::new(),
+    };
 
-                    state.socket = Some(Socket::new(&state.sock_addr)?);
+    state.seed = rng.get_seed();
 
-                    let session_token: u16 = player
-                        .input
-                        .parse()
-                        .map_err(|_| io::Error::new(io::ErrorKind::Other, "Invalid Number."))?;
+    shuffle_dictionary(state, &rng, state.filter.skew, state.filter.punctuation);
 
-                    player.sort_position =
-                        state.socket.as_mut().unwrap().join_session(session_token)?;
+    state.players[state.current_player].stats.start();
 
-                    state.session_token = Some(session_token);
+    state.screen = Screen::SinglePlayer;
 
-                    state.socket.as_ref().unwrap().init_reader()?;
+    Ok(())
+}
 
-                    state.dictionary = DICTIONARY.clone();
+fn host_session(state: &mut State) -> error::Result<()> {
+    let dictionary = state
+        .filter
+        .apply(&state.word_list)
+        .map_err(Error::Dictionary)?;
 
-                    let rng = fastrand::Rng::with_seed(state.session_token.unwrap().into());
+    let mut socket = Socket::new(&state.sock_addr)?;
 
-                    rng.shuffle(&mut state.dictionary);
+    let session_token = socket.create_session(dictionary::checksum(&dictionary))?;
 
-                    let mut prev_y: u16 = 0;
+    enter_session(
+        state,
+        socket,
+        session_token,
+        state.filter.clone(),
+        dictionary,
+        0,
+    )
+}
 
-                    for word in &mut state.dictionary {
-                        let mut y = rng.u16(0..state.rows - 1);
-                        if y == prev_y {
-                            y += 2;
-                            if y > state.rows - 1 {
-                                y -= 3;
-                            }
-                        }
-                        prev_y = y;
-                        word.y = y;
-                    }
+fn join_session(state: &mut State, code: &str) -> error::Result<()> {
+    let (session_token, session_filter) =
+        Filter::parse_session_code(code).map_err(Error::Protocol)?;
 
-                    player.input.clear();
+    let dictionary = session_filter
+        .apply(&state.word_list)
+        .map_err(Error::Dictionary)?;
 
-                    state.screen = Screen::Loading;
-                }
-                _ => (),
-            },
+    let mut socket = Socket::new(&state.sock_addr)?;
 
-            Event::Key(KeyEvent {
-                code: KeyCode::Backspace,
-                modifiers: KeyModifiers::NONE,
-            }) => {
-                if let Some(player) = state.players.get_mut(state.current_player) {
-                    player.input.pop();
-                    if let Some(socket) = &mut state.socket {
-                        socket.send_input('-')?;
-                    }
-                }
+    let sort_position = socket.join_session(session_token, dictionary::checksum(&dictionary))?;
+
+    enter_session(
+        state,
+        socket,
+        session_token,
+        session_filter,
+        dictionary,
+        sort_position,
+    )
+}
+
+/// Sets the game up for the session the Socket just created or joined and waits in the lobby.
+fn enter_session(
+    state: &mut State,
+    mut socket: Socket,
+    session_token: u16,
+    session_filter: Filter,
+    dictionary: Vec<Word>,
+    sort_position: u8,
+) -> error::Result<()> {
+    socket.init_reader()?;
+
+    // Only now that the session's created or joined, so a failure doesn't leave half a session
+    // behind
+    reset_state(state);
+
+    state.socket = Some(socket);
+
+    state.players[state.current_player].sort_position = sort_position;
+
+    state.session_token = Some(session_token);
+
+    state.session_filter = session_filter;
+
+    state.dictionary = dictionary;
+
+    state.seed = session_token.into();
+
+    let rng = fastrand::Rng::with_seed(state.seed);
+
+    shuffle_dictionary(
+        state,
+        &rng,
+        state.session_filter.skew,
+        state.session_filter.punctuation,
+    );
+
+    state.screen = Screen::Loading;
+
+    Ok(())
+}
+
+/// Loads the config file and the word list and applies them with the command line arguments
+/// on top. The defaults and the embedded word list are used for whatever can't be loaded.
+fn load_settings(state: &mut State) -> error::Result<()> {
+    let (config, config_err) = match config::load() {
+        Ok(config) => (config, None),
+        Err(err) => (config::Config::default(), Some(Error::Config(err))),
+    };
+
+    let args = &state.args;
+
+    state.filter = args.filter.clone().or(config.filter);
+    state.fixed_seed = args.seed;
+    state.speed = args.speed.unwrap_or(config.speed);
+    state.adaptive = args.adaptive || config.adaptive;
+    state.nickname = config.nickname;
+    state.keys = config.keys;
+    state.theme = config.theme;
+    state.sock_addr = args.server.clone().unwrap_or(config.server);
+
+    // A broken word list shouldn't keep anyone from playing, so it only gets reported
+    let dictionary_err = match args.dict.clone().or(config.dict) {
+        Some(path) => match dictionary::load(&path) {
+            Ok(word_list) => {
+                state.word_list = word_list;
+                None
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::Esc,
-                modifiers: KeyModifiers::NONE,
-            }) => {
-                if state.screen == Screen::Main {
-                    execute!(stdout, Clear(ClearType::All), Show)?;
-                    terminal::disable_raw_mode()?;
-                    std::process::exit(0);
-                }
-                reset_state(state);
-                state.screen = Screen::Main;
+            Err(err) => {
+                state.word_list = dictionary::embedded();
+                Some(Error::Dictionary(io::Error::new(
+                    err.kind(),
+                    format!("{} Using the embedded dictionary.", err),
+                )))
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::F(1),
-                modifiers: KeyModifiers::NONE,
-            }) => {
+        },
+        None => {
+            state.word_list = dictionary::embedded();
+            None
+        }
+    };
+
+    match config_err.or(dictionary_err) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Runs a Task, showing the error screen if it fails.
+fn run(state: &mut State, task: Task) {
+    let result = match &task {
+        Task::Settings => load_settings(state).map(|()| {
+            reset_state(state);
+            state.screen = Screen::Main;
+        }),
+        Task::SinglePlayer(seed) => start_single_player(state, *seed),
+        Task::Host => host_session(state),
+        Task::Join(code) => join_session(state, code),
+        Task::HighScores => storage::high_scores(10)
+            .map_err(Error::Storage)
+            .map(|high_scores| {
                 reset_state(state);
+                state.high_scores = high_scores;
+                state.screen = Screen::HighScores;
+            }),
+        Task::Record(run) => storage::record(run).map_err(Error::Storage).map(|()| {
+            state.error = None;
+            state.retry = None;
+            state.screen = Screen::Results;
+        }),
+    };
 
-                let rng = fastrand::Rng::new();
+    if let Err(err) = result {
+        state.fail(err, Some(task));
+    }
+}
 
-                rng.shuffle(&mut state.dictionary);
+/// Applies the Actions that arrived from the session up to `until`, in the order they arrived.
+fn apply_actions(state: &mut State, until: Instant) {
+    while let Some(action) = state
+        .socket
+        .as_mut()
+        .and_then(|socket| socket.next_action(until))
+    {
+        // Still taken off the Socket after the session, so they don't pile up
+        if matches!(state.screen, Screen::Loading | Screen::MultiPlayer) {
+            game::apply(state, action);
+        }
+    }
+}
 
-                let mut prev_y: u16 = 0;
+/// Runs the simulation until it has caught up with the clock. Actions from the session are
+/// applied right before the tick they arrived in, like they would've been with no lag.
+fn simulate(state: &mut State) {
+    let now = Instant::now();
+    state.lag = (state.lag + (now - state.last_tick)).min(game::MAX_LAG);
+    state.last_tick = now;
 
-                for word in &mut state.dictionary {
-                    let mut y = rng.u16(0..state.rows - 1);
-                    if y == prev_y {
-                        y += 2;
-                        if y > state.rows - 1 {
-                            y -= 3;
-                        }
-                    }
-                    prev_y = y;
-                    word.y = y;
-                }
+    // Where the next tick starts on the clock
+    let mut tick_start = now - state.lag;
+
+    while state.lag >= game::TICK {
+        apply_actions(state, tick_start + game::TICK);
+
+        tick_start += game::TICK;
+        state.lag -= game::TICK;
+
+        if game::tick(state) {
+            state.finish_run();
+            break;
+        }
+    }
+}
+
+/// Edits the current player's input and sends the Edit to the session, if there is one.
+/// Typed characters count towards the run's stats.
+fn edit_input(state: &mut State, edit: Edit) {
+    // Only the Join screen's session code and the runs take input. The other players only apply
+    // edits while the game's on, so one made in the lobby or after it would put them out of sync
+    if !matches!(
+        state.screen,
+        Screen::Join | Screen::SinglePlayer | Screen::MultiPlayer
+    ) {
+        return;
+    }
+
+    // The other players wouldn't see it, so the input stays as it is until the connection's back
+    // and the keystroke doesn't count either
+    if state.reconnect.is_some() {
+        return;
+    }
 
-                state.screen = Screen::SinglePlayer;
+    if let Some(player) = state.players.get_mut(state.current_player) {
+        if let Edit::Char(c) = edit {
+            if player.is_alive() && state.screen != Screen::Join {
+                let expected = state
+                    .dictionary
+                    .get(player.position)
+                    .and_then(|word| word.value.chars().nth(player.input.chars().count()));
+
+                player.record_keystroke(expected == Some(c));
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::F(2),
-                modifiers: KeyModifiers::NONE,
-            }) => {
-                reset_state(state);
+        }
+
+        player.edit(edit);
+    }
+
+    // A failed send marks the Socket as disconnected, check_connection takes it from there
+    if let Some(socket) = &mut state.socket {
+        let _ = socket.send_input(edit);
+    }
+}
+
+/// Keeps the connection to the session alive until the player leaves it, results screen
+/// included. While the session's still going it also starts getting back in once the connection
+/// drops and swaps in the new Socket when it's back.
+fn check_connection(state: &mut State) {
+    // Otherwise the server gives up on the connection and the Leave never makes it
+    if let Some(socket) = &mut state.socket {
+        socket.heartbeat();
+    }
 
-                state.socket = Some(Socket::new(&state.sock_addr)?);
+    if !matches!(state.screen, Screen::Loading | Screen::MultiPlayer) {
+        return;
+    }
 
-                state.session_token = Some(state.socket.as_mut().unwrap().create_session()?);
+    if let Some(reconnect) = state.reconnect.take() {
+        if !reconnect.is_finished() {
+            state.reconnect = Some(reconnect);
+            return;
+        }
 
-                state.socket.as_ref().unwrap().init_reader()?;
+        let socket = reconnect.finish().and_then(|mut socket| {
+            socket.init_reader()?;
+            Ok(socket)
+        });
 
-                state.dictionary = DICTIONARY.clone();
+        match socket {
+            Ok(socket) => {
+                // Whatever the old connection got in before it dropped
+                apply_actions(state, Instant::now());
+                state.socket = Some(socket);
+            }
+            Err(err) => {
+                state.socket = None;
+                state.fail(err, None);
+            }
+        }
 
-                let rng = fastrand::Rng::with_seed(state.session_token.unwrap().into());
+        return;
+    }
 
-                rng.shuffle(&mut state.dictionary);
+    let (Some(socket), Some(session_token)) = (&mut state.socket, state.session_token) else {
+        return;
+    };
 
-                let mut prev_y: u16 = 0;
+    if socket.is_connected() {
+        return;
+    }
 
-                for word in &mut state.dictionary {
-                    let mut y = rng.u16(0..state.rows - 1);
-                    if y == prev_y {
-                        y += 2;
-                        if y > state.rows - 1 {
-                            y -= 3;
-                        }
+    match socket.reader_error() {
+        // Garbage from the server doesn't get any better by reconnecting
+        Some(err @ Error::Protocol(_)) => {
+            state.socket = None;
+            state.fail(err, None);
+        }
+        cause => {
+            let position = state.players[state.current_player].sort_position;
+            state.reconnect = Some(Reconnect::start(
+                state.sock_addr.clone(),
+                session_token,
+                position,
+                socket.sequence(),
+                cause,
+            ));
+        }
+    }
+}
+
+fn main_loop(stdout: &mut Stdout, frame: &mut Frame, state: &mut State) -> io::Result<()> {
+    check_connection(state);
+
+    simulate(state);
+
+    frame.clear();
+
+    match state.screen {
+        Screen::Main => screens::main(frame, state),
+        Screen::SinglePlayer => screens::single_player(frame, state),
+        Screen::Join => screens::join(frame, state),
+        Screen::MultiPlayer => screens::multi_player(frame, state),
+        Screen::Loading => screens::loading(frame, state),
+        Screen::Results => screens::results(frame, state),
+        Screen::HighScores => screens::high_scores(frame, state),
+        Screen::Error => screens::error(frame, state),
+    };
+
+    if let Some(session_token) = state.session_token {
+        draw!(
+            frame,
+            MoveTo((state.columns as f32 * 0.1) as u16, state.rows),
+            PrintStyledContent("Session Token: ".green().bold()),
+            PrintStyledContent(
+                style(state.session_filter.session_code(session_token))
+                    .green()
+                    .bold()
+            )
+        );
+    }
+
+    screens::connection(frame, state);
+
+    // Only the cells that changed since the last frame are sent to the terminal
+    frame.render(stdout)?;
+
+    if event::poll(Duration::from_millis(16))? {
+        match event::read()? {
+            // Capitals and symbols like '?' or ':' come with Shift held down
+            Event::Key(KeyEvent {
+                code: KeyCode::Char(c),
+                modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            }) => edit_input(state, Edit::Char(c)),
+            Event::Key(KeyEvent {
+                code: KeyCode::Enter,
+                modifiers: KeyModifiers::NONE,
+            }) if state.screen == Screen::Join => {
+                let code = state.players[state.current_player].input.clone();
+                run(state, Task::Join(code));
+            }
+            Event::Key(KeyEvent {
+                code: KeyCode::Enter,
+                modifiers: KeyModifiers::NONE,
+            }) if state.screen == Screen::Error => {
+                if let Some(task) = state.retry.take() {
+                    run(state, task);
+                }
+            }
+            Event::Key(KeyEvent {
+                code: KeyCode::Backspace,
+                modifiers: KeyModifiers::NONE,
+            }) => edit_input(state, Edit::Backspace),
+            // Terminals send Ctrl+Backspace as Ctrl+H, or as Ctrl+W like some editors do
+            Event::Key(
+                KeyEvent {
+                    code: KeyCode::Char('w' | 'h'),
+                    modifiers: KeyModifiers::CONTROL,
+                }
+                | KeyEvent {
+                    code: KeyCode::Backspace,
+                    modifiers: KeyModifiers::CONTROL,
+                },
+            ) => edit_input(state, Edit::DeleteWord),
+            Event::Key(KeyEvent {
+                code: KeyCode::Char('u'),
+                modifiers: KeyModifiers::CONTROL,
+            }) => edit_input(state, Edit::Clear),
+            // Raw mode turns Ctrl+C into a key press instead of SIGINT
+            Event::Key(KeyEvent {
+                code: KeyCode::Char('c'),
+                modifiers: KeyModifiers::CONTROL,
+            }) => state.quit = true,
+            Event::Key(KeyEvent {
+                code,
+                modifiers: KeyModifiers::NONE,
+            }) if code == state.keys.back => match state.screen {
+                Screen::Main => state.quit = true,
+                Screen::SinglePlayer => state.quit_run(),
+                // Leaves the session, so the others don't watch the player's words escape
+                Screen::MultiPlayer => {
+                    state.socket = None;
+                    state.reconnect = None;
+
+                    // Out of lives the run's over already, only the others are still playing
+                    if state.players[state.current_player].is_alive() {
+                        state.quit_run();
+                    } else {
+                        state.finish_run();
                     }
-                    prev_y = y;
-                    word.y = y;
                 }
-
-                state.screen = Screen::Loading;
+                _ => {
+                    reset_state(state);
+                    state.screen = Screen::Main;
+                }
+            },
+            Event::Key(KeyEvent {
+                code,
+                modifiers: KeyModifiers::NONE,
+            }) if code == state.keys.single_player => {
+                run(state, Task::SinglePlayer(state.fixed_seed))
             }
             Event::Key(KeyEvent {
-                code: KeyCode::F(3),
+                code,
                 modifiers: KeyModifiers::NONE,
-            }) => {
+            }) if code == state.keys.replay
+                && state.screen == Screen::Results
+                && state.session_token.is_none() =>
+            {
+                run(state, Task::SinglePlayer(Some(state.seed)))
+            }
+            Event::Key(KeyEvent {
+                code,
+                modifiers: KeyModifiers::NONE,
+            }) if code == state.keys.host => run(state, Task::Host),
+            Event::Key(KeyEvent {
+                code,
+                modifiers: KeyModifiers::NONE,
+            }) if code == state.keys.join => {
                 reset_state(state);
                 state.screen = Screen::Join;
             }
+            Event::Key(KeyEvent {
+                code,
+                modifiers: KeyModifiers::NONE,
+            }) if code == state.keys.high_scores => run(state, Task::HighScores),
             Event::Resize(new_columns, new_rows) => {
+                frame.resize(new_columns, new_rows);
                 // Using nearest-neighbor interpolation to scale the frame up/down
                 let scale_x = new_columns as f32 / state.columns as f32;
                 let scale_y = new_rows as f32 / state.rows as f32;
@@ -241,33 +560,43 @@ fn main_loop(stdout: &mut Stdout, state: &mut State) -> io::Result<()> {
 }
 
 fn main() -> io::Result<()> {
-    let sock_addr = env::args().nth(1).unwrap_or("127.0.0.1:8080".to_owned());
+    let args = match cli::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("tip: {}\nTry 'tip --help' for more information.", err);
+            std::process::exit(2);
+        }
+    };
 
-    terminal::enable_raw_mode()?;
+    match args.command {
+        Command::Help => {
+            print!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Command::Version => {
+            println!("tip {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Command::Stats => return cli::print_stats(),
+        Command::Serve(addr) => return server::run(addr),
+        _ => (),
+    }
 
     // Get initial terminal size
     let (columns, rows) = terminal::size()?;
 
-    let mut state = State {
-        columns,
-        rows,
-        dictionary: DICTIONARY.clone(),
-        sock_addr,
-        screen: Screen::Main,
-        players: vec![],
-        instant: Instant::now(),
-        last_instant: 0,
-        current_player: 0,
-        session_token: None,
-        socket: None,
-        err: None,
-    };
+    let mut state = State::new(args, columns, rows);
+
+    // Loaded before the terminal is taken over, the word list might come from stdin
+    let settings = load_settings(&mut state);
+
+    // Restores the terminal however tip exits
+    let _guard = TerminalGuard::new()?;
 
     let mut stdout = stdout();
 
     queue!(
         stdout,
-        Hide,
         Clear(ClearType::All),
         MoveTo(columns / 2, rows / 2),
         Print("Shuffling Dictionary...")
@@ -275,9 +604,28 @@ fn main() -> io::Result<()> {
 
     stdout.flush()?;
 
-    loop {
-        if let Err(err) = main_loop(&mut stdout, &mut state) {
-            state.err = Some(Box::new(err));
+    match settings {
+        // Whatever was asked for on the command line has to wait until the settings are sorted out
+        Err(err) => state.fail(err, Some(Task::Settings)),
+        Ok(()) => {
+            let task = match &state.args.command {
+                Command::Play => Some(Task::SinglePlayer(state.fixed_seed)),
+                Command::Host => Some(Task::Host),
+                Command::Join(code) => Some(Task::Join(code.clone())),
+                _ => None,
+            };
+
+            if let Some(task) = task {
+                run(&mut state, task);
+            }
         }
     }
+
+    let mut frame = Frame::new(columns, rows);
+
+    while !state.quit {
+        main_loop(&mut stdout, &mut frame, &mut state)?;
+    }
+
+    Ok(())
 }
@@ -0,0 +1,553 @@
+use std::{
+    io::{self, Error, ErrorKind, Read, Write},
+    time::Duration,
+};
+
+/// Bumped whenever the messages change in a way older clients or servers can't deal with
+pub const PROTOCOL_VERSION: u16 = 7;
+
+/// How often the client sends a Heartbeat, the server answers every one of them
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Either side gives up on the connection when the other one's been quiet for this long
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the server keeps a player's slot after their connection dropped, and how long the
+/// client keeps trying to get back into it
+pub const RECONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Optional features this build supports, one bit each. Only the ones both sides have get used,
+/// so new features don't need a new protocol version. None so far.
+pub const CAPABILITIES: u32 = 0;
+
+/// Frames are `[length: u16][tag: u8][payload]` with the big endian length counting the tag and
+/// the payload, anything longer than this is garbage.
+const MAX_FRAME_LENGTH: usize = 256;
+
+/// A change a player makes to their input
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edit {
+    // Types a character, '-' included
+    Char(char),
+    // Removes the last character
+    Backspace,
+    // Removes the last word, like Ctrl+W in a shell
+    DeleteWord,
+    // Removes the whole input
+    Clear,
+}
+
+/// The first byte of every frame, one per message and edit. Values are never reused, a
+/// message that goes away leaves a gap.
+mod tag {
+    pub const CREATE: u8 = 1;
+    pub const JOIN: u8 = 2;
+    pub const INPUT_CHAR: u8 = 3;
+    pub const CREATED: u8 = 4;
+    pub const JOINED: u8 = 5;
+    pub const PLAYER_JOINED: u8 = 6;
+    pub const PLAYER_LEFT: u8 = 7;
+    pub const PLAYER_INPUT_CHAR: u8 = 8;
+    pub const FORWARD: u8 = 9;
+    pub const HELLO: u8 = 10;
+    pub const INPUT_BACKSPACE: u8 = 11;
+    pub const INPUT_DELETE_WORD: u8 = 12;
+    pub const INPUT_CLEAR: u8 = 13;
+    pub const PLAYER_INPUT_BACKSPACE: u8 = 14;
+    pub const PLAYER_INPUT_DELETE_WORD: u8 = 15;
+    pub const PLAYER_INPUT_CLEAR: u8 = 16;
+    pub const REJOIN: u8 = 17;
+    pub const REJOINED: u8 = 18;
+    pub const HEARTBEAT: u8 = 19;
+    pub const LEAVE: u8 = 20;
+    pub const DICTIONARY_MISMATCH: u8 = 21;
+    pub const BROADCAST: u8 = 22;
+}
+
+/// Everything the client and the session server send each other
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    // Both ways, the first message on every connection. The client says hello with what it
+    // speaks and the server answers with what it speaks, or closes the connection after it
+    // when the protocol versions don't match. The server answers with the capabilities both
+    // sides have in common
+    Hello {
+        protocol: u16,
+        capabilities: u32,
+        version: String,
+    },
+    // Client to server, the first message after the handshake. Both carry the checksum of the
+    // player's word list, everyone in a session has to play with the same one
+    Create(u64),
+    Join(u16, u64),
+    // Client to server, gets a player whose connection dropped back into their session, along
+    // with the sequence number of the last Broadcast they got
+    Rejoin(u16, u8, u32),
+    // Client to server, how the player changed their input
+    Input(Edit),
+    // Server to client, answers Create with the new session's token
+    Created(u16),
+    // Server to client, answers Join with the player's position in the session
+    Joined(u8),
+    // Server to client, answers Join when the session plays with a different word list, the
+    // connection's closed right after
+    DictionaryMismatch,
+    // Server to client, answers Rejoin, followed by every Broadcast the player missed meanwhile
+    Rejoined,
+    // Server to client, wraps a message sent to everyone in the session. They're numbered so a
+    // player who gets back in after the connection dropped doesn't miss or repeat any
+    Broadcast(u32, Box<Message>),
+    // Server to client, someone's in the session, including the players that were already there
+    PlayerJoined(u8),
+    // Server to client, the player with this index left
+    PlayerLeft(u8),
+    // Server to client, the player with this index changed their input
+    PlayerInput(u8, Edit),
+    // Server to client, moves the words forward
+    Forward,
+    // Both ways, keeps a quiet connection from looking like a dead one
+    Heartbeat,
+    // Client to server, the player left the session and isn't coming back
+    Leave,
+}
+
+impl Message {
+    fn tag(&self) -> u8 {
+        match self {
+            Message::Hello { .. } => tag::HELLO,
+            Message::Create(_) => tag::CREATE,
+            Message::Join(..) => tag::JOIN,
+            Message::Rejoin(..) => tag::REJOIN,
+            Message::Input(Edit::Char(_)) => tag::INPUT_CHAR,
+            Message::Input(Edit::Backspace) => tag::INPUT_BACKSPACE,
+            Message::Input(Edit::DeleteWord) => tag::INPUT_DELETE_WORD,
+            Message::Input(Edit::Clear) => tag::INPUT_CLEAR,
+            Message::Created(_) => tag::CREATED,
+            Message::Joined(_) => tag::JOINED,
+            Message::DictionaryMismatch => tag::DICTIONARY_MISMATCH,
+            Message::Rejoined => tag::REJOINED,
+            Message::PlayerJoined(_) => tag::PLAYER_JOINED,
+            Message::PlayerLeft(_) => tag::PLAYER_LEFT,
+            Message::PlayerInput(_, Edit::Char(_)) => tag::PLAYER_INPUT_CHAR,
+            Message::PlayerInput(_, Edit::Backspace) => tag::PLAYER_INPUT_BACKSPACE,
+            Message::PlayerInput(_, Edit::DeleteWord) => tag::PLAYER_INPUT_DELETE_WORD,
+            Message::PlayerInput(_, Edit::Clear) => tag::PLAYER_INPUT_CLEAR,
+            Message::Forward => tag::FORWARD,
+            Message::Heartbeat => tag::HEARTBEAT,
+            Message::Leave => tag::LEAVE,
+            Message::Broadcast(..) => tag::BROADCAST,
+        }
+    }
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+fn push_char(payload: &mut Vec<u8>, c: char) {
+    payload.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+fn parse_char(bytes: &[u8]) -> io::Result<char> {
+    let s = std::str::from_utf8(bytes).map_err(|_| invalid("Invalid character."))?;
+
+    let mut chars = s.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(invalid("Expected a single character.")),
+    }
+}
+
+fn parse_u64(bytes: &[u8]) -> u64 {
+    let mut be_bytes = [0; 8];
+    be_bytes.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(be_bytes)
+}
+
+/// Encodes the Message as a whole frame, length included.
+pub fn encode(message: &Message) -> Vec<u8> {
+    let mut payload = vec![];
+
+    push_message(&mut payload, message);
+
+    let mut frame = (payload.len() as u16).to_be_bytes().to_vec();
+    frame.extend(payload);
+    frame
+}
+
+/// Appends the Message's tag and payload.
+fn push_message(payload: &mut Vec<u8>, message: &Message) {
+    payload.push(message.tag());
+
+    match *message {
+        Message::Hello {
+            protocol,
+            capabilities,
+            ref version,
+        } => {
+            payload.extend_from_slice(&protocol.to_be_bytes());
+            payload.extend_from_slice(&capabilities.to_be_bytes());
+            payload.extend_from_slice(version.as_bytes());
+        }
+        Message::Forward
+        | Message::Rejoined
+        | Message::Heartbeat
+        | Message::Leave
+        | Message::DictionaryMismatch => (),
+        Message::Create(dictionary) => payload.extend_from_slice(&dictionary.to_be_bytes()),
+        Message::Join(session_token, dictionary) => {
+            payload.extend_from_slice(&session_token.to_be_bytes());
+            payload.extend_from_slice(&dictionary.to_be_bytes());
+        }
+        Message::Created(session_token) => payload.extend_from_slice(&session_token.to_be_bytes()),
+        Message::Rejoin(session_token, position, sequence) => {
+            payload.extend_from_slice(&session_token.to_be_bytes());
+            payload.push(position);
+            payload.extend_from_slice(&sequence.to_be_bytes());
+        }
+        Message::Broadcast(sequence, ref message) => {
+            payload.extend_from_slice(&sequence.to_be_bytes());
+            push_message(payload, message);
+        }
+        Message::Input(Edit::Char(c)) => push_char(payload, c),
+        Message::Input(_) => (),
+        Message::Joined(position)
+        | Message::PlayerJoined(position)
+        | Message::PlayerLeft(position) => payload.push(position),
+        Message::PlayerInput(index, edit) => {
+            payload.push(index);
+            if let Edit::Char(c) = edit {
+                push_char(payload, c);
+            }
+        }
+    }
+}
+
+/// Decodes the frame at the start of the buffer. Returns the Message along with the number of
+/// bytes it took up, or None if the buffer doesn't hold the whole frame yet.
+pub fn decode(buffer: &[u8]) -> io::Result<Option<(Message, usize)>> {
+    let Some(length) = buffer.get(..2) else {
+        return Ok(None);
+    };
+
+    let length = u16::from_be_bytes([length[0], length[1]]) as usize;
+
+    if length == 0 || length > MAX_FRAME_LENGTH {
+        return Err(invalid(format!("Invalid frame length {}.", length)));
+    }
+
+    let Some(frame) = buffer.get(2..2 + length) else {
+        return Ok(None);
+    };
+
+    Ok(Some((decode_message(frame)?, 2 + length)))
+}
+
+/// Decodes a Message from its tag and payload.
+fn decode_message(frame: &[u8]) -> io::Result<Message> {
+    let Some((&tag, payload)) = frame.split_first() else {
+        return Err(invalid("Missing message tag."));
+    };
+
+    let expect_length = |expected: usize| {
+        if payload.len() == expected {
+            Ok(())
+        } else {
+            Err(invalid(format!(
+                "Expected {} bytes for message {}, got {}.",
+                expected,
+                tag,
+                payload.len()
+            )))
+        }
+    };
+
+    let message = match tag {
+        tag::HELLO if payload.len() >= 6 => Message::Hello {
+            protocol: u16::from_be_bytes([payload[0], payload[1]]),
+            capabilities: u32::from_be_bytes([payload[2], payload[3], payload[4], payload[5]]),
+            version: String::from_utf8(payload[6..].to_vec())
+                .map_err(|_| invalid("Invalid version."))?,
+        },
+        tag::HELLO => return Err(invalid("Hello is too short.")),
+        tag::CREATE => {
+            expect_length(8)?;
+            Message::Create(parse_u64(payload))
+        }
+        tag::JOIN => {
+            expect_length(10)?;
+            Message::Join(
+                u16::from_be_bytes([payload[0], payload[1]]),
+                parse_u64(&payload[2..]),
+            )
+        }
+        tag::REJOIN => {
+            expect_length(7)?;
+            Message::Rejoin(
+                u16::from_be_bytes([payload[0], payload[1]]),
+                payload[2],
+                u32::from_be_bytes([payload[3], payload[4], payload[5], payload[6]]),
+            )
+        }
+        tag::BROADCAST if payload.len() > 4 => match decode_message(&payload[4..])? {
+            Message::Broadcast(..) => return Err(invalid("Broadcast inside a Broadcast.")),
+            message => Message::Broadcast(
+                u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]),
+                Box::new(message),
+            ),
+        },
+        tag::BROADCAST => return Err(invalid("Broadcast is too short.")),
+        tag::INPUT_CHAR => Message::Input(Edit::Char(parse_char(payload)?)),
+        tag::INPUT_BACKSPACE => {
+            expect_length(0)?;
+            Message::Input(Edit::Backspace)
+        }
+        tag::INPUT_DELETE_WORD => {
+            expect_length(0)?;
+            Message::Input(Edit::DeleteWord)
+        }
+        tag::INPUT_CLEAR => {
+            expect_length(0)?;
+            Message::Input(Edit::Clear)
+        }
+        tag::CREATED => {
+            expect_length(2)?;
+            Message::Created(u16::from_be_bytes([payload[0], payload[1]]))
+        }
+        tag::JOINED => {
+            expect_length(1)?;
+            Message::Joined(payload[0])
+        }
+        tag::DICTIONARY_MISMATCH => {
+            expect_length(0)?;
+            Message::DictionaryMismatch
+        }
+        tag::REJOINED => {
+            expect_length(0)?;
+            Message::Rejoined
+        }
+        tag::PLAYER_JOINED => {
+            expect_length(1)?;
+            Message::PlayerJoined(payload[0])
+        }
+        tag::PLAYER_LEFT => {
+            expect_length(1)?;
+            Message::PlayerLeft(payload[0])
+        }
+        tag::PLAYER_INPUT_CHAR => match payload.split_first() {
+            Some((&index, c)) => Message::PlayerInput(index, Edit::Char(parse_char(c)?)),
+            None => return Err(invalid("Missing player index.")),
+        },
+        tag::PLAYER_INPUT_BACKSPACE => {
+            expect_length(1)?;
+            Message::PlayerInput(payload[0], Edit::Backspace)
+        }
+        tag::PLAYER_INPUT_DELETE_WORD => {
+            expect_length(1)?;
+            Message::PlayerInput(payload[0], Edit::DeleteWord)
+        }
+        tag::PLAYER_INPUT_CLEAR => {
+            expect_length(1)?;
+            Message::PlayerInput(payload[0], Edit::Clear)
+        }
+        tag::FORWARD => {
+            expect_length(0)?;
+            Message::Forward
+        }
+        tag::HEARTBEAT => {
+            expect_length(0)?;
+            Message::Heartbeat
+        }
+        tag::LEAVE => {
+            expect_length(0)?;
+            Message::Leave
+        }
+        _ => return Err(invalid(format!("Unknown message {}.", tag))),
+    };
+
+    Ok(message)
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Message) -> io::Result<()> {
+    writer.write_all(&encode(message))
+}
+
+/// Reads whole Messages from a stream, no matter how TCP splits up or merges the frames.
+pub struct Reader<R> {
+    inner: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buffer: vec![],
+        }
+    }
+
+    /// Blocks until the next Message arrives, None means the other side closed the connection.
+    pub fn read_message(&mut self) -> io::Result<Option<Message>> {
+        let mut chunk = [0u8; 512];
+
+        loop {
+            if let Some((message, used)) = decode(&self.buffer)? {
+                self.buffer.drain(..used);
+                return Ok(Some(message));
+            }
+
+            let read = self.inner.read(&mut chunk)?;
+
+            if read == 0 {
+                return if self.buffer.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Connection closed in the middle of a message.",
+                    ))
+                };
+            }
+
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages() -> Vec<Message> {
+        vec![
+            Message::Hello {
+                protocol: PROTOCOL_VERSION,
+                capabilities: CAPABILITIES,
+                version: "0.4.0".to_owned(),
+            },
+            Message::Create(0x0123_4567_89ab_cdef),
+            Message::Join(4242, u64::MAX),
+            Message::DictionaryMismatch,
+            Message::Rejoin(4242, 3, 99),
+            Message::Rejoined,
+            Message::Input(Edit::Char('a')),
+            Message::Input(Edit::Char('-')),
+            Message::Input(Edit::Backspace),
+            Message::Input(Edit::DeleteWord),
+            Message::Input(Edit::Clear),
+            Message::Created(u16::MAX),
+            Message::Joined(3),
+            Message::PlayerJoined(0),
+            Message::PlayerLeft(7),
+            Message::PlayerInput(2, Edit::Char('z')),
+            Message::PlayerInput(1, Edit::Char('ß')),
+            Message::PlayerInput(0, Edit::Backspace),
+            Message::PlayerInput(4, Edit::DeleteWord),
+            Message::PlayerInput(5, Edit::Clear),
+            Message::Forward,
+            Message::Heartbeat,
+            Message::Leave,
+            Message::Broadcast(1, Box::new(Message::Forward)),
+            Message::Broadcast(u32::MAX, Box::new(Message::PlayerInput(1, Edit::Char('ß')))),
+        ]
+    }
+
+    /// Hands out the data a few bytes at a time, like a slow connection would.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(self.data.len()).min(buf.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for message in messages() {
+            let frame = encode(&message);
+            assert_eq!(decode(&frame).unwrap(), Some((message, frame.len())));
+        }
+    }
+
+    #[test]
+    fn distinct_tags() {
+        let mut tags: Vec<u8> = messages().iter().map(Message::tag).collect();
+        tags.sort_unstable();
+        tags.dedup();
+
+        // Input and PlayerInput both come with two different characters, and there are two
+        // Broadcasts
+        assert_eq!(tags.len(), messages().len() - 3);
+    }
+
+    #[test]
+    fn incomplete_frames() {
+        for message in messages() {
+            let frame = encode(&message);
+            for end in 0..frame.len() {
+                assert_eq!(decode(&frame[..end]).unwrap(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn split_and_merged_reads() {
+        let stream: Vec<u8> = messages().iter().flat_map(encode).collect();
+
+        for step in [1, 2, 3, 7, stream.len()] {
+            let mut reader = Reader::new(Trickle {
+                data: &stream,
+                step,
+            });
+
+            for message in messages() {
+                assert_eq!(reader.read_message().unwrap(), Some(message));
+            }
+
+            assert_eq!(reader.read_message().unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let frame = encode(&Message::PlayerInput(1, Edit::Char('x')));
+
+        let mut reader = Reader::new(&frame[..frame.len() - 1]);
+
+        assert_eq!(
+            reader.read_message().unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn invalid_frames() {
+        // Zero length, too long, unknown tags, a Broadcast of nothing, a Broadcast in a Broadcast,
+        // wrong payload size, two characters, a Hello with half a protocol version, one without
+        // capabilities, a backspace with a payload and a player's clear without an index
+        for frame in [
+            &[0, 0][..],
+            &[0xff, 0xff],
+            &[0, 1, 200],
+            &[0, 1, 0],
+            &[0, 1, 23],
+            &[0, 5, 22, 0, 0, 0, 1],
+            &[0, 11, 22, 0, 0, 0, 1, 22, 0, 0, 0, 2, 9],
+            &[0, 2, 2, 1],
+            &[0, 3, 3, b'a', b'b'],
+            &[0, 2, 10, 0],
+            &[0, 5, 10, 0, 7, 0, 0],
+            &[0, 2, 11, b'a'],
+            &[0, 1, 16],
+        ] {
+            assert_eq!(decode(frame).unwrap_err().kind(), ErrorKind::InvalidData);
+        }
+    }
+}
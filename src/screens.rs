@@ -1,143 +1,114 @@
-use super::types::{Action, Player, Screen, State};
+use super::{
+    config::key_name,
+    frame::{draw, Frame},
+    protocol::{HEARTBEAT_INTERVAL, RECONNECT_TIMEOUT},
+    storage::Run,
+    types::{Player, Screen, State, Word, VISIBLE_WORDS},
+};
 use crossterm::{
     cursor::MoveTo,
-    queue,
     style::{style, Attribute, Color, Print, PrintStyledContent, Stylize},
-    terminal::{Clear, ClearType},
-};
-use std::{
-    io::{self, Stdout},
-    iter,
 };
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+pub fn main(frame: &mut Frame, state: &State) {
+    let keys = &state.keys;
 
-pub fn main(stdout: &mut Stdout, state: &State) -> io::Result<()> {
     let (x, y) = (
         (state.columns as f32 * 0.4) as u16,
         (state.rows as f32 * 0.41) as u16,
     );
 
-    queue!(
-        stdout,
-        Clear(ClearType::All),
+    draw!(
+        frame,
         MoveTo(x, y),
-        PrintStyledContent("F1 - Single Player".green().bold()),
+        PrintStyledContent(
+            style(format!("{} - Single Player", key_name(keys.single_player)))
+                .green()
+                .bold()
+        ),
         MoveTo(x, y + 1),
-        PrintStyledContent("F2 - Create New Multiplayer Session".yellow().bold()),
+        PrintStyledContent(
+            style(format!(
+                "{} - Create New Multiplayer Session",
+                key_name(keys.host)
+            ))
+            .yellow()
+            .bold()
+        ),
         MoveTo(x, y + 2),
-        PrintStyledContent("F3 - Join Session".blue().bold()),
+        PrintStyledContent(
+            style(format!("{} - Join Session", key_name(keys.join)))
+                .blue()
+                .bold()
+        ),
         MoveTo(x, y + 3),
-        PrintStyledContent("ESC - Quit".red().bold())
-    )?;
-
-    Ok(())
+        PrintStyledContent(
+            style(format!("{} - High Scores", key_name(keys.high_scores)))
+                .magenta()
+                .bold()
+        ),
+        MoveTo(x, y + 4),
+        PrintStyledContent(
+            style(format!("{} - Quit", key_name(keys.back)))
+                .red()
+                .bold()
+        )
+    );
 }
 
-pub fn single_player(stdout: &mut Stdout, state: &mut State) -> io::Result<()> {
-    print_help(stdout, &state)?;
+pub fn single_player(frame: &mut Frame, state: &State) {
+    print_help(frame, state);
 
     let columns = state.columns;
 
-    let player = &mut state.players[state.current_player];
+    let text = state.theme.text;
 
-    let elapsed_millis = state.instant.elapsed().as_millis();
-
-    let should_go_forward: bool = elapsed_millis - state.last_instant > 500;
-
-    let mut add_x: u16 = 4;
-
-    for (i, word) in state.dictionary[player.position..player.position + 4]
-        .iter_mut()
-        .enumerate()
-    {
-        let mut correct_chars = 0;
-
-        for (j, c) in word.value.chars().enumerate() {
-            let mut color = Color::White;
-            let mut boldness = Attribute::NormalIntensity;
+    draw!(
+        frame,
+        MoveTo(0, 0),
+        PrintStyledContent("Seed: ".with(text).bold()),
+        PrintStyledContent(style(state.seed).with(text))
+    );
 
-            if i == 0 {
-                if let Some(d) = player.input.chars().nth(j) {
-                    if d == c {
-                        color = Color::Green;
-                        correct_chars += 1;
-                        boldness = Attribute::Bold;
-                    } else {
-                        color = Color::Red;
-                        boldness = Attribute::Bold;
-                    }
-                }
-            }
+    let player = &state.players[state.current_player];
 
-            queue!(
-                stdout,
-                MoveTo(word.x + j as u16, word.y),
-                PrintStyledContent(style(c).with(color).attribute(boldness))
-            )?;
-        }
+    let words = state
+        .dictionary
+        .iter()
+        .skip(player.position)
+        .take(state.pace.visible_words);
 
-        if correct_chars == word.value.len() || word.x >= columns {
-            player.input.clear();
-            player.position += 1;
-        }
+    print_words(frame, state, player, words, |word| word.y);
 
-        if should_go_forward {
-            word.x += add_x;
-            state.last_instant = elapsed_millis;
-        }
+    print_lives(
+        frame,
+        columns.saturating_sub(10),
+        0,
+        player.lives,
+        state.theme.incorrect,
+    );
 
-        add_x -= 1;
+    if state.adaptive {
+        draw!(
+            frame,
+            MoveTo(columns.saturating_sub(26), 0),
+            PrintStyledContent("Pace: ".with(text).bold()),
+            PrintStyledContent(style(format!("{:.0} WPM", player.rolling_wpm())).with(text))
+        );
+    } else {
+        draw!(
+            frame,
+            MoveTo(columns.saturating_sub(22), 0),
+            PrintStyledContent("Level: ".with(text).bold()),
+            PrintStyledContent(style(player.level()).with(text))
+        );
     }
-
-    Ok(())
 }
 
-pub fn multi_player(stdout: &mut Stdout, state: &mut State) -> io::Result<()> {
-    print_help(stdout, state)?;
-
-    let socket = state.socket.as_ref().unwrap();
-
-    let mut should_go_forward = false;
-
-    for action in socket.actions().drain(..) {
-        match action {
-            Action::Join(position) => {
-                let mut player = Player::default();
-                player.sort_position = position;
-                state.players.push(player);
-                state.players.sort_by(|player_a, player_b| {
-                    player_a.sort_position.cmp(&player_b.sort_position)
-                });
-                for (i, player) in state.players.iter().enumerate() {
-                    if player.current_player {
-                        state.current_player = i;
-                        break;
-                    }
-                }
-            }
-            Action::Left(position) => {
-                state.players.remove(position);
-                state.players.sort_by(|player_a, player_b| {
-                    player_a.sort_position.cmp(&player_b.sort_position)
-                });
-                for (i, player) in state.players.iter().enumerate() {
-                    if player.current_player {
-                        state.current_player = i;
-                        break;
-                    }
-                }
-            }
-            Action::Input((position, c)) => {
-                if let Some(player) = state.players.get_mut(position) {
-                    if c == '-' {
-                        player.input.pop();
-                    } else {
-                        player.input.push(c);
-                    }
-                }
-            }
-            Action::Forward => should_go_forward = true,
-        };
-    }
+pub fn multi_player(frame: &mut Frame, state: &State) {
+    print_help(frame, state);
 
     let (columns, rows) = (state.columns, state.rows as f32);
 
@@ -145,132 +116,113 @@ pub fn multi_player(stdout: &mut Stdout, state: &mut State) -> io::Result<()> {
 
     let space_per_player = ((rows - 2.0) / players_len as f32) as u16;
 
-    let line: String = iter::repeat('-').take(columns.into()).collect();
+    let line = "-".repeat(columns.into());
 
-    // Might use multithreading to calculate each player's section,
-    // but that might be overengineering too, so we'll see.
-    for (i, player) in state.players.iter_mut().enumerate() {
+    for (i, player) in state.players.iter().enumerate() {
         let y_start = i as u16 * space_per_player;
 
         let y_end = y_start + space_per_player;
 
-        let color = match i {
-            0 => Color::Blue,
-            1 => Color::Red,
-            2 => Color::Green,
-            3 => Color::Yellow,
-            _ => Color::White,
-        };
+        let color = state.theme.player(i);
 
-        let print_you = if player.current_player { " (You)" } else { "" };
+        let print_you = match (&state.nickname, player.current_player) {
+            (Some(nickname), true) => format!(" ({})", nickname),
+            (None, true) => " (You)".to_owned(),
+            _ => String::new(),
+        };
 
-        queue!(
-            stdout,
+        draw!(
+            frame,
             MoveTo(0, y_end),
             PrintStyledContent(style(&line).with(color)),
             MoveTo(5, y_end),
             PrintStyledContent("Player ".with(color)),
             PrintStyledContent(style(i + 1).with(color)),
-            PrintStyledContent(style(print_you).with(color))
-        )?;
-
-        let mut add_x: u16 = 4;
-
-        for (j, word) in state.dictionary[player.position..player.position + 4]
-            .iter_mut()
-            .enumerate()
-        {
-            let mut correct_chars = 0;
-
-            let (y_start, y_end) = (y_start + 1, y_end - 1);
-
-            let word_y = ((word.y as f32 / rows) * (y_end - y_start) as f32) as u16 + y_start;
-
-            for (n, c) in word.value.chars().enumerate() {
-                let mut color = Color::White;
-                let mut boldness = Attribute::NormalIntensity;
-
-                if j == 0 {
-                    if let Some(d) = player.input.chars().nth(n) {
-                        if d == c {
-                            color = Color::Green;
-                            correct_chars += 1;
-                            boldness = Attribute::Bold;
-                        } else {
-                            color = Color::Red;
-                            boldness = Attribute::Bold;
-                        }
-                    }
-                }
+            PrintStyledContent(style(print_you).with(color)),
+            Print(' ')
+        );
+
+        print_lives(
+            frame,
+            columns.saturating_sub(10),
+            y_end,
+            player.lives,
+            color,
+        );
+
+        if !player.is_alive() {
+            draw!(
+                frame,
+                MoveTo((columns / 2).saturating_sub(4), (y_start + y_end) / 2),
+                PrintStyledContent("Game Over".with(color).bold())
+            );
+            continue;
+        }
 
-                queue!(
-                    stdout,
-                    MoveTo(word.x + n as u16, word_y),
-                    PrintStyledContent(style(c).with(color).attribute(boldness))
-                )?;
-            }
+        let words = state
+            .dictionary
+            .iter()
+            .skip(player.position)
+            .take(VISIBLE_WORDS);
 
-            if correct_chars == word.value.len() || word.x >= columns {
-                player.input.clear();
-                player.position += 1;
-            }
+        let (y_start, y_end) = (y_start + 1, y_end.saturating_sub(1));
 
-            if should_go_forward {
-                word.x += add_x;
-                add_x -= 1;
-            }
-        }
+        // Squeeze the rows of the whole screen into the player's lane
+        print_words(frame, state, player, words, |word| {
+            ((word.y as f32 / rows) * y_end.saturating_sub(y_start) as f32) as u16 + y_start
+        });
     }
-
-    Ok(())
 }
 
-pub fn loading(stdout: &mut Stdout, state: &mut State) -> io::Result<()> {
-    let socket = state.socket.as_ref().unwrap();
-
-    for action in socket.actions().drain(..) {
-        match action {
-            Action::Join(position) => {
-                let mut player = Player::default();
-                player.sort_position = position;
-                state.players.push(player);
-                state.players.sort_by(|player_a, player_b| {
-                    player_a.sort_position.cmp(&player_b.sort_position)
-                });
-                for (i, player) in state.players.iter().enumerate() {
-                    if player.current_player {
-                        state.current_player = i;
-                        break;
-                    }
-                }
-            }
-            Action::Left(position) => {
-                state.players.remove(position);
-                state.players.sort_by(|player_a, player_b| {
-                    player_a.sort_position.cmp(&player_b.sort_position)
-                });
-                for (i, player) in state.players.iter().enumerate() {
-                    if player.current_player {
-                        state.current_player = i;
-                        break;
-                    }
+/// Prints the visible words of a Player, coloring the typed part of the first one.
+fn print_words<'a>(
+    frame: &mut Frame,
+    state: &State,
+    player: &Player,
+    words: impl Iterator<Item = &'a Word>,
+    word_y: impl Fn(&Word) -> u16,
+) {
+    let typed: Vec<&str> = player.input.graphemes(true).collect();
+
+    for (i, word) in words.enumerate() {
+        let y = word_y(word);
+
+        let mut x = word.x;
+
+        for (j, grapheme) in word.value.graphemes(true).enumerate() {
+            let mut color = state.theme.text;
+            let mut boldness = Attribute::NormalIntensity;
+
+            if i == 0 {
+                if let Some(&typed) = typed.get(j) {
+                    boldness = Attribute::Bold;
+                    color = if typed == grapheme {
+                        state.theme.correct
+                    } else {
+                        state.theme.incorrect
+                    };
                 }
             }
-            Action::Forward => {
-                state.screen = Screen::MultiPlayer;
-                return Ok(());
-            }
-            _ => (),
-        };
+
+            draw!(
+                frame,
+                MoveTo(x, y),
+                PrintStyledContent(style(grapheme).with(color).attribute(boldness))
+            );
+
+            x = x.saturating_add(grapheme.width() as u16);
+        }
     }
+}
 
+pub fn loading(frame: &mut Frame, state: &State) {
     let (columns, rows) = (
         (state.columns as f32 * 0.35) as u16,
         (state.rows as f32 * 0.4) as u16,
     );
 
-    queue!(
-        stdout,
+    draw!(
+        frame,
         MoveTo(columns, rows),
         PrintStyledContent(
             "Waiting 10 seconds for other players to join."
@@ -278,33 +230,29 @@ pub fn loading(stdout: &mut Stdout, state: &mut State) -> io::Result<()> {
                 .bold()
         ),
         MoveTo(columns, rows + 2)
-    )?;
+    );
 
     for (i, player) in state.players.iter().enumerate() {
-        let color = match i {
-            0 => Color::Blue,
-            1 => Color::Red,
-            2 => Color::Green,
-            3 => Color::Yellow,
-            _ => Color::White,
-        };
+        let color = state.theme.player(i);
 
-        let print_you = if player.current_player { " (You)" } else { "" };
+        let print_you = match (&state.nickname, player.current_player) {
+            (Some(nickname), true) => format!(" ({})", nickname),
+            (None, true) => " (You)".to_owned(),
+            _ => String::new(),
+        };
 
-        queue!(
-            stdout,
+        draw!(
+            frame,
             PrintStyledContent("Player ".with(color).bold()),
             PrintStyledContent(style(i + 1).with(color).bold()),
             PrintStyledContent(style(print_you).with(color).bold()),
             Print(' ')
-        )?;
+        );
     }
-
-    Ok(())
 }
 
-pub fn join(stdout: &mut Stdout, state: &mut State) -> io::Result<()> {
-    print_help(stdout, &state)?;
+pub fn join(frame: &mut Frame, state: &State) {
+    print_help(frame, state);
 
     let (columns, rows) = (state.columns as f32, state.rows as f32);
 
@@ -313,36 +261,36 @@ pub fn join(stdout: &mut Stdout, state: &mut State) -> io::Result<()> {
     let mut y_start = (rows * 0.4) as u16;
     let y_end = y_start + 2;
 
-    queue!(
-        stdout,
+    draw!(
+        frame,
         MoveTo(x_start, y_start - 1),
         PrintStyledContent("Enter Session Code".bold()),
-    )?;
+    );
 
     for x in x_start..x_end {
-        queue!(
-            stdout,
+        draw!(
+            frame,
             MoveTo(x, y_start),
             Print('-'),
             MoveTo(x, y_end),
             Print('-')
-        )?;
+        );
     }
 
     x_end -= 1;
     y_start += 1;
 
-    queue!(
-        stdout,
+    draw!(
+        frame,
         MoveTo(x_start, y_start),
         Print('|'),
         MoveTo(x_end, y_start),
         Print('|')
-    )?;
+    );
 
     let player = state.players.get(state.current_player).unwrap();
 
-    x_end = x_end - 1;
+    x_end -= 1;
 
     x_start += 1;
 
@@ -351,18 +299,214 @@ pub fn join(stdout: &mut Stdout, state: &mut State) -> io::Result<()> {
         if x > x_end {
             break;
         }
-        queue!(stdout, MoveTo(x, y_start), Print(c))?;
+        draw!(frame, MoveTo(x, y_start), Print(c));
+    }
+}
+
+pub fn results(frame: &mut Frame, state: &State) {
+    print_help(frame, state);
+
+    let player = &state.players[state.current_player];
+
+    let stats = &player.stats;
+
+    let (x, y) = (
+        (state.columns as f32 * 0.4) as u16,
+        (state.rows as f32 * 0.35) as u16,
+    );
+
+    draw!(
+        frame,
+        MoveTo(x, y),
+        PrintStyledContent(if player.is_alive() {
+            "Results".bold()
+        } else {
+            "Game Over".red().bold()
+        }),
+        MoveTo(x, y + 2),
+        PrintStyledContent("WPM: ".green().bold()),
+        Print(format!(
+            "{:.0} ({:.0} gross)",
+            stats.net_wpm(),
+            stats.gross_wpm()
+        )),
+        MoveTo(x, y + 3),
+        PrintStyledContent("Accuracy: ".green().bold()),
+        Print(format!("{:.1}%", stats.accuracy())),
+        MoveTo(x, y + 4),
+        PrintStyledContent("Words: ".green().bold()),
+        Print(format!(
+            "{} completed, {} missed",
+            stats.completed_words, stats.missed_words
+        )),
+        MoveTo(x, y + 5),
+        PrintStyledContent("Keystrokes: ".green().bold()),
+        Print(format!(
+            "{} correct, {} incorrect",
+            stats.correct_keystrokes, stats.incorrect_keystrokes
+        )),
+        MoveTo(x, y + 6),
+        PrintStyledContent("Time: ".green().bold()),
+        Print(format!("{:.1}s", stats.elapsed().as_secs_f32())),
+        MoveTo(x, y + 7),
+        PrintStyledContent("Seed: ".green().bold()),
+        Print(state.seed),
+        MoveTo(x, y + 9),
+        PrintStyledContent(
+            style(format!(
+                "{} - Play Again",
+                key_name(state.keys.single_player)
+            ))
+            .yellow()
+            .bold()
+        )
+    );
+
+    // Multiplayer runs can't be replayed on your own, the seed is the session token
+    if state.session_token.is_none() {
+        draw!(
+            frame,
+            MoveTo(x, y + 10),
+            PrintStyledContent(
+                style(format!(
+                    "{} - Replay This Seed",
+                    key_name(state.keys.replay)
+                ))
+                .yellow()
+                .bold()
+            )
+        );
+    }
+}
+
+pub fn high_scores(frame: &mut Frame, state: &State) {
+    print_help(frame, state);
+
+    let (x, y) = (
+        (state.columns as f32 * 0.2) as u16,
+        (state.rows as f32 * 0.2) as u16,
+    );
+
+    draw!(
+        frame,
+        MoveTo(x, y),
+        PrintStyledContent("High Scores".bold()),
+        MoveTo(x, y + 2),
+        PrintStyledContent(Run::header().green().bold())
+    );
+
+    if state.high_scores.is_empty() {
+        draw!(frame, MoveTo(x, y + 3), Print("No runs recorded yet."));
     }
 
-    Ok(())
+    for (i, run) in state.high_scores.iter().enumerate() {
+        draw!(frame, MoveTo(x, y + 3 + i as u16), Print(run.row(i + 1)));
+    }
 }
 
-fn print_help(stdout: &mut Stdout, state: &State) -> io::Result<()> {
-    queue!(
-        stdout,
-        MoveTo(0, state.rows),
-        PrintStyledContent("ESC - Go Back".yellow().bold())
-    )?;
+pub fn error(frame: &mut Frame, state: &State) {
+    let Some(error) = &state.error else {
+        return;
+    };
+
+    let (x, y) = (
+        (state.columns as f32 * 0.2) as u16,
+        (state.rows as f32 * 0.4) as u16,
+    );
+
+    draw!(
+        frame,
+        MoveTo(x, y),
+        PrintStyledContent(error.title().red().bold()),
+        MoveTo(x, y + 2),
+        Print(error),
+        MoveTo(x, y + 3),
+        Print(error.hint())
+    );
+
+    let mut y = y + 5;
+
+    if state.retry.is_some() {
+        draw!(
+            frame,
+            MoveTo(x, y),
+            PrintStyledContent("Enter - Retry".green().bold())
+        );
+        y += 1;
+    }
+
+    draw!(
+        frame,
+        MoveTo(x, y),
+        PrintStyledContent(
+            style(format!("{} - Back to Menu", key_name(state.keys.back)))
+                .yellow()
+                .bold()
+        ),
+        MoveTo(x, y + 1),
+        PrintStyledContent("Ctrl+C - Quit".red().bold())
+    );
+}
+
+/// Shown on top of the session when the server's been quiet for a while or the connection's
+/// down, along with why it went down.
+pub fn connection(frame: &mut Frame, state: &State) {
+    if !matches!(state.screen, Screen::Loading | Screen::MultiPlayer) {
+        return;
+    }
+
+    let (text, cause) = match (&state.reconnect, &state.socket) {
+        (Some(reconnect), _) => {
+            let left = RECONNECT_TIMEOUT.saturating_sub(reconnect.since.elapsed());
+            (
+                format!(" Connection lost, reconnecting… {}s ", left.as_secs()),
+                reconnect.cause.as_ref().map(ToString::to_string),
+            )
+        }
+        (None, Some(socket)) if socket.last_seen().elapsed() > HEARTBEAT_INTERVAL * 2 => (
+            format!(
+                " No answer from the server for {}s ",
+                socket.last_seen().elapsed().as_secs()
+            ),
+            None,
+        ),
+        _ => return,
+    };
+
+    let (x, y) = (state.columns / 2, state.rows / 2);
+
+    draw!(
+        frame,
+        MoveTo(x.saturating_sub(text.width() as u16 / 2), y),
+        PrintStyledContent(style(&text).black().on_yellow().bold())
+    );
+
+    if let Some(cause) = cause {
+        draw!(
+            frame,
+            MoveTo(x.saturating_sub(cause.width() as u16 / 2), y + 1),
+            PrintStyledContent(style(&cause).yellow())
+        );
+    }
+}
+
+fn print_lives(frame: &mut Frame, x: u16, y: u16, lives: u8, color: Color) {
+    draw!(
+        frame,
+        MoveTo(x, y),
+        PrintStyledContent("Lives: ".with(color).bold()),
+        PrintStyledContent(style(lives).with(color).bold())
+    );
+}
 
-    Ok(())
+fn print_help(frame: &mut Frame, state: &State) {
+    draw!(
+        frame,
+        MoveTo(0, state.rows),
+        PrintStyledContent(
+            style(format!("{} - Go Back", key_name(state.keys.back)))
+                .yellow()
+                .bold()
+        )
+    );
 }
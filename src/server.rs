@@ -0,0 +1,685 @@
+use super::protocol::{
+    self, Message, Reader, CAPABILITIES, HEARTBEAT_TIMEOUT, PROTOCOL_VERSION, RECONNECT_TIMEOUT,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Error, ErrorKind, Write},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{self, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// How long a freshly created session waits for other players before the game starts.
+const LOBBY_DURATION: Duration = Duration::from_secs(10);
+
+/// Time between two Forward ticks, same pace as Single Player.
+const TICK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How many bytes of the most recent Broadcasts a session keeps for players who get back in,
+/// anyone who missed more than that can't catch up.
+const MAX_REPLAY: usize = 64 * 1024;
+
+/// Writes a client can be behind by before it counts as gone.
+const MAX_QUEUED: usize = 1024;
+
+type Sessions = Arc<Mutex<HashMap<u16, Session>>>;
+
+/// Sends everything meant for a client from a thread of its own, so nothing waits for the
+/// network while the sessions are locked and a client that stops reading can't hold up the rest.
+struct Writer {
+    queue: SyncSender<Vec<u8>>,
+    // Only used to close the connection
+    stream: TcpStream,
+}
+
+impl Writer {
+    fn spawn(stream: &TcpStream) -> io::Result<Self> {
+        let (queue, queued) = mpsc::sync_channel::<Vec<u8>>(MAX_QUEUED);
+
+        let mut out = stream.try_clone()?;
+
+        // Ends once the Writer's dropped and everything queued is sent, or the connection fails
+        thread::spawn(move || {
+            for bytes in queued {
+                if out.write_all(&bytes).is_err() {
+                    let _ = out.shutdown(Shutdown::Both);
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            queue,
+            stream: stream.try_clone()?,
+        })
+    }
+
+    /// Queues the bytes without waiting for them to be sent. A client that can't keep up gets
+    /// its connection closed, same as if it had dropped.
+    fn send(&self, bytes: Vec<u8>) {
+        if self.queue.try_send(bytes).is_err() {
+            self.close();
+        }
+    }
+
+    fn send_message(&self, message: &Message) {
+        self.send(protocol::encode(message));
+    }
+
+    /// Closes the connection, the client's own thread notices and takes care of the disconnect.
+    fn close(&self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+struct Client {
+    position: u8,
+    // None while the connection's down and the player might still come back
+    writer: Option<Writer>,
+    // Counts the player's connections, so an old one going down doesn't drop the new one
+    connection: u32,
+    // Sequence number of the last Broadcast from before the player joined
+    joined: u32,
+}
+
+impl Client {
+    fn new(position: u8, writer: Writer, joined: u32) -> Self {
+        Self {
+            position,
+            writer: Some(writer),
+            connection: 0,
+            joined,
+        }
+    }
+}
+
+/// A Broadcast that's kept for players who get back in after their connection dropped
+struct Sent {
+    sequence: u32,
+    // The position of the player it wasn't meant for
+    except: Option<u8>,
+    frame: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Session {
+    // Always sorted by position, which matches the order the clients keep their players in
+    clients: Vec<Client>,
+    next_position: u8,
+    started: bool,
+    // Checksum of the host's word list, players with another one would see different words
+    dictionary: u64,
+    // Sequence number of the last Broadcast
+    sequence: u32,
+    // The most recent Broadcasts, up to MAX_REPLAY bytes of them
+    replay: VecDeque<Sent>,
+    replay_size: usize,
+}
+
+impl Session {
+    /// Sends the Message to every client except the one with the `except` position. Whatever
+    /// doesn't make it through is replayed once the player's back.
+    fn broadcast(&mut self, message: Message, except: Option<u8>) {
+        self.sequence += 1;
+
+        let frame = protocol::encode(&Message::Broadcast(self.sequence, Box::new(message)));
+
+        for client in &mut self.clients {
+            if Some(client.position) == except {
+                continue;
+            }
+            if let Some(writer) = &client.writer {
+                writer.send(frame.clone());
+            }
+        }
+
+        self.replay_size += frame.len();
+        self.replay.push_back(Sent {
+            sequence: self.sequence,
+            except,
+            frame,
+        });
+
+        while self.replay_size > MAX_REPLAY {
+            match self.replay.pop_front() {
+                Some(sent) => self.replay_size -= sent.frame.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Every Broadcast meant for the player with the `to` position after the one with the
+    /// `since` sequence number, or None if some of them aren't kept anymore.
+    fn missed(&self, since: u32, to: u8) -> Option<Vec<u8>> {
+        if self
+            .replay
+            .front()
+            .is_some_and(|sent| sent.sequence > since.saturating_add(1))
+        {
+            return None;
+        }
+
+        Some(
+            self.replay
+                .iter()
+                .filter(|sent| sent.sequence > since && sent.except != Some(to))
+                .flat_map(|sent| sent.frame.iter().copied())
+                .collect(),
+        )
+    }
+
+    /// Sends the Message to the client with the `to` position only.
+    fn send(&mut self, message: Message, to: u8) {
+        if let Some(Client {
+            writer: Some(writer),
+            ..
+        }) = self.clients.iter().find(|client| client.position == to)
+        {
+            writer.send_message(&message);
+        }
+    }
+
+    fn index_of(&self, position: u8) -> Option<usize> {
+        self.clients
+            .iter()
+            .position(|client| client.position == position)
+    }
+}
+
+/// Runs the session server until the listener fails.
+pub fn run(addr: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+
+    println!("Listening on {}", listener.local_addr()?);
+
+    serve(listener)
+}
+
+fn serve(listener: TcpListener) -> io::Result<()> {
+    let sessions: Sessions = Arc::default();
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        let sessions = sessions.clone();
+
+        thread::spawn(move || handle_client(stream, &sessions));
+    }
+
+    Ok(())
+}
+
+fn handle_client(stream: TcpStream, sessions: &Sessions) -> io::Result<()> {
+    // Clients send a Heartbeat every second, a connection that's quiet for longer is gone. One
+    // that doesn't take what's sent to it for as long is gone too.
+    stream.set_read_timeout(Some(HEARTBEAT_TIMEOUT))?;
+    stream.set_write_timeout(Some(HEARTBEAT_TIMEOUT))?;
+
+    let mut reader = Reader::new(stream.try_clone()?);
+
+    let (protocol, capabilities) = match reader.read_message()? {
+        Some(Message::Hello {
+            protocol,
+            capabilities,
+            ..
+        }) => (protocol, capabilities),
+        Some(_) => return Err(Error::new(ErrorKind::InvalidData, "Expected Hello.")),
+        None => return Ok(()),
+    };
+
+    protocol::write_message(
+        &mut &stream,
+        &Message::Hello {
+            protocol: PROTOCOL_VERSION,
+            capabilities: capabilities & CAPABILITIES,
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+        },
+    )?;
+
+    // The client can tell the player what's wrong from the Hello it just got
+    if protocol != PROTOCOL_VERSION {
+        return Ok(());
+    }
+
+    let writer = Writer::spawn(&stream)?;
+
+    // A refused request drops the Writer, which closes the connection once its answer is sent
+    let (session_token, position, connection) = match reader.read_message()? {
+        Some(Message::Create(dictionary)) => (create_session(dictionary, writer, sessions), 0, 0),
+        Some(Message::Join(session_token, dictionary)) => {
+            match join_session(session_token, dictionary, writer, sessions) {
+                Some(position) => (session_token, position, 0),
+                // Closing the connection lets the client know that it couldn't join
+                None => return Ok(()),
+            }
+        }
+        Some(Message::Rejoin(session_token, position, sequence)) => {
+            match rejoin_session(session_token, position, sequence, writer, sessions) {
+                Some(connection) => (session_token, position, connection),
+                None => return Ok(()),
+            }
+        }
+        Some(_) => return Err(Error::new(ErrorKind::InvalidData, "Unknown Request.")),
+        None => return Ok(()),
+    };
+
+    let left = input_loop(&mut reader, session_token, position, sessions);
+
+    // A player who said goodbye isn't coming back, no need to keep their slot
+    let grace = match left {
+        Ok(true) => Duration::ZERO,
+        _ => RECONNECT_TIMEOUT,
+    };
+
+    disconnect(session_token, position, connection, grace, sessions);
+
+    left.map(drop)
+}
+
+/// Every edit a client makes to their input after creating/joining a session gets forwarded to
+/// the other players along with the sender's index, Heartbeats get answered.
+/// Returns whether the player left the session, rather than just losing the connection.
+fn input_loop(
+    reader: &mut Reader<TcpStream>,
+    session_token: u16,
+    position: u8,
+    sessions: &Sessions,
+) -> io::Result<bool> {
+    loop {
+        let edit = match reader.read_message()? {
+            Some(Message::Input(edit)) => Some(edit),
+            Some(Message::Heartbeat) => None,
+            Some(Message::Leave) => return Ok(true),
+            Some(_) => return Err(Error::new(ErrorKind::InvalidData, "Unexpected Message.")),
+            None => return Ok(false),
+        };
+
+        let mut sessions = sessions.lock().unwrap();
+
+        let session = match sessions.get_mut(&session_token) {
+            Some(session) => session,
+            None => return Ok(false),
+        };
+
+        match edit {
+            Some(edit) => {
+                if let Some(index) = session.index_of(position) {
+                    session.broadcast(Message::PlayerInput(index as u8, edit), Some(position));
+                }
+            }
+            // Answered right away, so the client knows the connection's alive too
+            None => session.send(Message::Heartbeat, position),
+        }
+    }
+}
+
+fn create_session(dictionary: u64, writer: Writer, sessions: &Sessions) -> u16 {
+    let mut locked = sessions.lock().unwrap();
+
+    let mut session_token = fastrand::u16(..);
+
+    while locked.contains_key(&session_token) {
+        session_token = fastrand::u16(..);
+    }
+
+    writer.send_message(&Message::Created(session_token));
+
+    let mut session = Session {
+        dictionary,
+        ..Default::default()
+    };
+
+    session.clients.push(Client::new(0, writer, 0));
+    session.next_position = 1;
+
+    locked.insert(session_token, session);
+
+    let sessions = sessions.clone();
+
+    thread::spawn(move || tick_session(session_token, &sessions));
+
+    session_token
+}
+
+/// Returns the position of the new player or None if the session doesn't exist,
+/// already started, is full or plays with a different word list.
+fn join_session(
+    session_token: u16,
+    dictionary: u64,
+    writer: Writer,
+    sessions: &Sessions,
+) -> Option<u8> {
+    let mut sessions = sessions.lock().unwrap();
+
+    let session = match sessions.get_mut(&session_token) {
+        Some(session) if !session.started && session.next_position < u8::MAX => session,
+        _ => return None,
+    };
+
+    if session.dictionary != dictionary {
+        writer.send_message(&Message::DictionaryMismatch);
+        return None;
+    }
+
+    let position = session.next_position;
+
+    writer.send_message(&Message::Joined(position));
+
+    // Let the new player know about everyone that's already in the session
+    for client in &session.clients {
+        writer.send_message(&Message::PlayerJoined(client.position));
+    }
+
+    session.broadcast(Message::PlayerJoined(position), None);
+
+    session
+        .clients
+        .push(Client::new(position, writer, session.sequence));
+    session.next_position += 1;
+
+    Some(position)
+}
+
+/// Puts a player whose connection dropped back into their slot and catches them up on every
+/// Broadcast after the `sequence` one. Returns the new connection's number, or None if the
+/// slot's gone or the player missed too much.
+fn rejoin_session(
+    session_token: u16,
+    position: u8,
+    sequence: u32,
+    writer: Writer,
+    sessions: &Sessions,
+) -> Option<u32> {
+    let mut sessions = sessions.lock().unwrap();
+
+    let session = sessions.get_mut(&session_token)?;
+
+    let index = session.index_of(position)?;
+
+    // Whatever was sent before the player joined isn't for them
+    let since = sequence.max(session.clients[index].joined);
+
+    let missed = session.missed(since, position)?;
+
+    writer.send_message(&Message::Rejoined);
+    writer.send(missed);
+
+    let client = &mut session.clients[index];
+
+    // The player noticed the old connection dropping before the server did
+    if let Some(old) = client.writer.replace(writer) {
+        old.close();
+    }
+
+    client.connection += 1;
+
+    Some(client.connection)
+}
+
+/// Keeps the player's slot for `grace` after their connection dropped, and lets everyone else
+/// know they left if they don't come back in time.
+fn disconnect(
+    session_token: u16,
+    position: u8,
+    connection: u32,
+    grace: Duration,
+    sessions: &Sessions,
+) {
+    {
+        let mut sessions = sessions.lock().unwrap();
+
+        let Some(client) = sessions.get_mut(&session_token).and_then(|session| {
+            let index = session.index_of(position)?;
+            session.clients.get_mut(index)
+        }) else {
+            return;
+        };
+
+        // Already replaced by a newer connection
+        if client.connection != connection {
+            return;
+        }
+
+        client.writer = None;
+    }
+
+    if grace.is_zero() {
+        leave_session(session_token, position, connection, sessions);
+        return;
+    }
+
+    let sessions = sessions.clone();
+
+    thread::spawn(move || {
+        thread::sleep(grace);
+        leave_session(session_token, position, connection, &sessions);
+    });
+}
+
+fn leave_session(session_token: u16, position: u8, connection: u32, sessions: &Sessions) {
+    let mut sessions = sessions.lock().unwrap();
+
+    let session = match sessions.get_mut(&session_token) {
+        Some(session) => session,
+        None => return,
+    };
+
+    if let Some(index) = session.index_of(position) {
+        let client = &session.clients[index];
+
+        // The player came back in time
+        if client.writer.is_some() || client.connection != connection {
+            return;
+        }
+
+        session.clients.remove(index);
+        session.broadcast(Message::PlayerLeft(index as u8), None);
+    }
+
+    if session.clients.is_empty() {
+        sessions.remove(&session_token);
+    }
+}
+
+/// Waits for the lobby to fill up, then keeps the session going until every player left.
+fn tick_session(session_token: u16, sessions: &Sessions) {
+    thread::sleep(LOBBY_DURATION);
+
+    loop {
+        {
+            let mut sessions = sessions.lock().unwrap();
+
+            let session = match sessions.get_mut(&session_token) {
+                Some(session) => session,
+                None => return,
+            };
+
+            session.started = true;
+            session.broadcast(Message::Forward, None);
+        }
+
+        thread::sleep(TICK_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{protocol::Edit, socket::Socket};
+    use std::time::Instant;
+
+    /// Runs a server on a free loopback port for the rest of the test run.
+    fn start() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || serve(listener));
+
+        addr
+    }
+
+    type Connection = (TcpStream, Reader<TcpStream>);
+
+    /// Connects and says hello, so the next message is the request.
+    fn connect(addr: &str) -> Connection {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(HEARTBEAT_TIMEOUT)).unwrap();
+
+        let mut reader = Reader::new(stream.try_clone().unwrap());
+
+        protocol::write_message(
+            &mut stream,
+            &Message::Hello {
+                protocol: PROTOCOL_VERSION,
+                capabilities: CAPABILITIES,
+                version: "test".to_owned(),
+            },
+        )
+        .unwrap();
+
+        assert!(matches!(
+            reader.read_message().unwrap(),
+            Some(Message::Hello { .. })
+        ));
+
+        (stream, reader)
+    }
+
+    fn send(stream: &mut TcpStream, message: Message) {
+        protocol::write_message(stream, &message).unwrap();
+    }
+
+    fn read(reader: &mut Reader<TcpStream>) -> Message {
+        reader.read_message().unwrap().unwrap()
+    }
+
+    /// A session with a host and a second player at position 1, returns the session token.
+    fn session(addr: &str) -> (u16, Connection, Connection) {
+        let (mut host, mut host_reader) = connect(addr);
+        send(&mut host, Message::Create(0));
+
+        let Message::Created(session_token) = read(&mut host_reader) else {
+            panic!("Expected Created.");
+        };
+
+        let (mut player, mut player_reader) = connect(addr);
+        send(&mut player, Message::Join(session_token, 0));
+
+        assert_eq!(read(&mut player_reader), Message::Joined(1));
+        assert_eq!(read(&mut player_reader), Message::PlayerJoined(0));
+        assert_eq!(
+            read(&mut host_reader),
+            Message::Broadcast(1, Box::new(Message::PlayerJoined(1)))
+        );
+
+        (session_token, (host, host_reader), (player, player_reader))
+    }
+
+    #[test]
+    fn rejoin_replays_what_was_missed() {
+        let addr = start();
+        let (session_token, (mut host, mut host_reader), (mut player, mut player_reader)) =
+            session(&addr);
+
+        let typed = |sequence, index, c| {
+            Message::Broadcast(
+                sequence,
+                Box::new(Message::PlayerInput(index, Edit::Char(c))),
+            )
+        };
+
+        // Broadcasts 2 to 4 go to everyone but the player, 5 and 6 to the player only
+        for (sequence, c) in (2..).zip("abc".chars()) {
+            send(&mut player, Message::Input(Edit::Char(c)));
+            assert_eq!(read(&mut host_reader), typed(sequence, 1, c));
+        }
+
+        for c in "xy".chars() {
+            send(&mut host, Message::Input(Edit::Char(c)));
+        }
+
+        assert_eq!(read(&mut player_reader), typed(5, 0, 'x'));
+        assert_eq!(read(&mut player_reader), typed(6, 0, 'y'));
+
+        for (since, missed) in [
+            (5, vec![typed(6, 0, 'y')]),
+            (0, vec![typed(5, 0, 'x'), typed(6, 0, 'y')]),
+        ] {
+            let (mut player, mut player_reader) = connect(&addr);
+            send(&mut player, Message::Rejoin(session_token, 1, since));
+
+            assert_eq!(read(&mut player_reader), Message::Rejoined);
+
+            for message in missed {
+                assert_eq!(read(&mut player_reader), message);
+            }
+
+            // Answered after the replay, so nothing else came with it
+            send(&mut player, Message::Heartbeat);
+            assert_eq!(read(&mut player_reader), Message::Heartbeat);
+        }
+    }
+
+    #[test]
+    fn rejoin_fails_once_the_replay_moved_on() {
+        let addr = start();
+        let (session_token, (mut host, mut host_reader), (player, _)) = session(&addr);
+
+        player.shutdown(Shutdown::Both).unwrap();
+
+        // Way more than MAX_REPLAY holds
+        let typed: Vec<u8> = (0..MAX_REPLAY)
+            .flat_map(|_| protocol::encode(&Message::Input(Edit::Char('a'))))
+            .collect();
+
+        host.write_all(&typed).unwrap();
+
+        // Answered once every keystroke's been broadcast
+        send(&mut host, Message::Heartbeat);
+        assert_eq!(read(&mut host_reader), Message::Heartbeat);
+
+        let (mut player, mut player_reader) = connect(&addr);
+        send(&mut player, Message::Rejoin(session_token, 1, 1));
+
+        assert!(matches!(player_reader.read_message(), Ok(None) | Err(_)));
+    }
+
+    #[test]
+    fn dropped_socket_leaves_right_away() {
+        let addr = start();
+        let (mut host, mut host_reader) = connect(&addr);
+        send(&mut host, Message::Create(0));
+
+        let Message::Created(session_token) = read(&mut host_reader) else {
+            panic!("Expected Created.");
+        };
+
+        let mut socket = Socket::new(&addr).unwrap();
+        let position = socket.join_session(session_token, 0).unwrap();
+        socket.init_reader().unwrap();
+
+        assert_eq!(
+            read(&mut host_reader),
+            Message::Broadcast(1, Box::new(Message::PlayerJoined(position)))
+        );
+
+        let start = Instant::now();
+        drop(socket);
+
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // Well before the slot would've been given up on otherwise
+        assert_eq!(
+            read(&mut host_reader),
+            Message::Broadcast(2, Box::new(Message::PlayerLeft(1)))
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -1,133 +1,506 @@
-use super::types::Action;
+use super::{
+    error::{self, Error},
+    protocol::{
+        self, Edit, Message, Reader, CAPABILITIES, HEARTBEAT_INTERVAL, HEARTBEAT_TIMEOUT,
+        PROTOCOL_VERSION, RECONNECT_TIMEOUT,
+    },
+    types::Action,
+};
 use std::{
-    io::{self, Error, ErrorKind, Read, Write},
-    net::{TcpStream, ToSocketAddrs},
+    io::{self, ErrorKind},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc,
     },
-    thread,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
+const OLD_SERVER: &str = "The server didn't answer the handshake, it's probably an older tip.";
+
+/// Pause between two attempts to get back into the session
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long leaving a session may take, a dead connection isn't worth waiting for
+const LEAVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Actions the reader thread can be ahead of the game by, it waits for the game to catch up
+/// instead of piling up more
+const ACTION_CAPACITY: usize = 1024;
+
 /// Small Abstraction to keep the code cleaner
 pub struct Socket {
     stream: TcpStream,
-    actions: Arc<Mutex<Vec<Action>>>,
-    should_drop: Arc<AtomicBool>,
+    // Handed over to the reader thread, it might already hold messages read during the handshake
+    reader: Option<Reader<TcpStream>>,
+    // Everything the session sent along with when it arrived, filled by the reader thread
+    actions: Option<Receiver<(Instant, Action)>>,
+    // Taken off the channel already but not due yet
+    pending: Option<(Instant, Action)>,
+    // Set once the server put the player into a session
+    in_session: bool,
+    // Cleared once reading from or writing to the stream fails, or the server hangs up
+    connected: Arc<AtomicBool>,
+    // Reads the server's messages once the session's joined, ends along with the connection
+    reader_thread: Option<JoinHandle<io::Result<()>>>,
+    // When the Socket was created, last_seen counts from here
+    epoch: Instant,
+    // Milliseconds from the epoch to the last message from the server, Heartbeats included
+    last_seen: Arc<AtomicU64>,
+    // Sequence number of the last Broadcast that made it into the actions
+    sequence: Arc<AtomicU32>,
+    last_heartbeat: Instant,
 }
 
+/// Leaves the session, closes the connection and waits for the reader thread to finish, so
+/// nothing's left behind however often the player leaves and joins sessions.
 impl Drop for Socket {
     fn drop(&mut self) {
-        self.should_drop.store(true, Ordering::Release);
+        // Otherwise the server keeps the player's slot in case they come back
+        if self.in_session && self.is_connected() {
+            let _ = self.stream.set_write_timeout(Some(LEAVE_TIMEOUT));
+            let _ = self.send(Message::Leave);
+        }
+
+        // Wakes the reader thread up, whether it's waiting for the server or for room in the
+        // channel
+        let _ = self.stream.shutdown(Shutdown::Both);
+        self.actions = None;
+
+        if let Some(reader_thread) = self.reader_thread.take() {
+            let _ = reader_thread.join();
+        }
+    }
+}
+
+/// Garbage from the server is a protocol error, anything else means the connection's gone.
+fn read_error(err: io::Error) -> Error {
+    if err.kind() == ErrorKind::InvalidData {
+        Error::Protocol(err)
+    } else {
+        Error::Network(err)
     }
 }
 
 impl Socket {
-    pub fn new(addr: impl ToSocketAddrs) -> io::Result<Self> {
-        let stream = TcpStream::connect(addr)?;
+    pub fn new(addr: impl ToSocketAddrs) -> error::Result<Self> {
+        Self::with_stream(TcpStream::connect(addr).map_err(Error::Network)?)
+    }
+
+    /// Like `new`, but gives up on connecting once the `timeout` is over instead of waiting for
+    /// the OS to, which can take minutes.
+    pub fn connect_timeout(addr: impl ToSocketAddrs, timeout: Duration) -> error::Result<Self> {
+        let deadline = Instant::now() + timeout;
+
+        let mut last_err = io::Error::new(ErrorKind::TimedOut, "Connecting timed out.");
+
+        for addr in addr.to_socket_addrs().map_err(Error::Network)? {
+            let left = deadline.saturating_duration_since(Instant::now());
+
+            if left.is_zero() {
+                break;
+            }
+
+            match TcpStream::connect_timeout(&addr, left) {
+                Ok(stream) => return Self::with_stream(stream),
+                Err(err) => last_err = err,
+            }
+        }
+
+        Err(Error::Network(last_err))
+    }
+
+    fn with_stream(stream: TcpStream) -> error::Result<Self> {
+        // The server answers every Heartbeat, a connection that's quiet for longer is gone.
+        // Servers from before the handshake don't answer it at all, this catches those too.
+        stream
+            .set_read_timeout(Some(HEARTBEAT_TIMEOUT))
+            .map_err(Error::Network)?;
+
+        let reader = Reader::new(stream.try_clone().map_err(Error::Network)?);
 
-        Ok(Self {
+        let mut socket = Self {
             stream,
-            actions: Arc::new(Mutex::new(vec![])),
-            should_drop: Arc::new(AtomicBool::new(false)),
-        })
+            reader: Some(reader),
+            actions: None,
+            pending: None,
+            in_session: false,
+            connected: Arc::new(AtomicBool::new(true)),
+            reader_thread: None,
+            epoch: Instant::now(),
+            last_seen: Arc::new(AtomicU64::new(0)),
+            sequence: Arc::new(AtomicU32::new(0)),
+            last_heartbeat: Instant::now(),
+        };
+
+        socket.handshake()?;
+
+        Ok(socket)
+    }
+
+    /// Makes sure the server speaks the same protocol version before anything else is sent.
+    fn handshake(&mut self) -> error::Result<()> {
+        self.send(Message::Hello {
+            protocol: PROTOCOL_VERSION,
+            capabilities: CAPABILITIES,
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+        })?;
+
+        let reply = self.reply();
+
+        let mismatch = |message: String| {
+            Err(Error::Mismatch(io::Error::new(
+                ErrorKind::InvalidData,
+                message,
+            )))
+        };
+
+        match reply {
+            // The server only keeps what both sides have, anything else means it's confused
+            Ok(Some(Message::Hello {
+                protocol,
+                capabilities,
+                ..
+            })) if protocol == PROTOCOL_VERSION => match capabilities & !CAPABILITIES {
+                0 => Ok(()),
+                _ => Err(Error::Protocol(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "The server offered capabilities tip didn't ask for.",
+                ))),
+            },
+            Ok(Some(Message::Hello {
+                protocol, version, ..
+            })) => mismatch(format!(
+                "The server is tip {} with protocol {}, this is tip {} with protocol {}.",
+                version,
+                protocol,
+                env!("CARGO_PKG_VERSION"),
+                PROTOCOL_VERSION
+            )),
+            // Older servers hang up, answer with garbage or don't answer at all
+            Ok(_) | Err(Error::Protocol(_)) => mismatch(OLD_SERVER.to_owned()),
+            Err(Error::Network(err))
+                if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                mismatch(OLD_SERVER.to_owned())
+            }
+            Err(err) => Err(err),
+        }
     }
 
     fn reader_loop(
-        mut stream: TcpStream,
-        actions: &Mutex<Vec<Action>>,
-        should_drop: &AtomicBool,
+        mut reader: Reader<TcpStream>,
+        actions: SyncSender<(Instant, Action)>,
+        epoch: Instant,
+        last_seen: &AtomicU64,
+        last_sequence: &AtomicU32,
     ) -> io::Result<()> {
-        // If Mutex couldn't be locked, actions will be backed up here and get added the next time
-        // we acquire the Mutex lock.
-        // Note: Not 100% sure that this is needed, just made sense when I thought about it.
-        // I think that it makes sense to keep receieving data and store it, then when the Mutex
-        // lock is acquired, we can add the already stored data + new data to it, thus we'll
-        // probably block the rendering thread a lot less.
-        // This might cause stuff like a user typing something and instead of each character
-        // that they type lighting up individually, 2 characters might light up at the same time,
-        // so I might just ditch this method entirely later.
-        let mut buffer = [0u8; 5];
-        let mut actions_backup: Vec<Action> = vec![];
-
-        while !should_drop.load(Ordering::Acquire) {
-            if stream.read(&mut buffer)? < 1 {
-                break;
-            }
+        while let Some(message) = reader.read_message()? {
+            last_seen.store(epoch.elapsed().as_millis() as u64, Ordering::Release);
 
-            let action = if buffer[0] == b'+' {
-                Action::Forward
-            } else {
-                match &buffer[..4] {
-                    b"Join" => Action::Join(buffer[4]),
-                    b"Left" => Action::Left(buffer[4].into()),
-                    _ => Action::Input((buffer[0].into(), buffer[1].into())),
-                }
+            // Only the players already in the session come without a sequence number, right
+            // after joining
+            let (sequence, message) = match message {
+                Message::Broadcast(sequence, message) => (Some(sequence), *message),
+                message => (None, message),
             };
 
-            if let Ok(mut vec) = actions.try_lock() {
-                vec.extend(actions_backup.drain(..).as_slice());
-                vec.push(action);
-                continue;
+            let action = match message {
+                Message::PlayerJoined(position) => Action::Join(position),
+                Message::PlayerLeft(index) => Action::Left(index.into()),
+                Message::PlayerInput(index, edit) => Action::Input((index.into(), edit)),
+                Message::Forward => Action::Forward,
+                // Heartbeats only count for last_seen, and requests and replies have no
+                // business showing up here
+                _ => continue,
+            };
+
+            // Only blocks when the game's ACTION_CAPACITY actions behind, and fails once the
+            // Socket's gone
+            if actions.send((Instant::now(), action)).is_err() {
+                break;
             }
 
-            actions_backup.push(action);
+            if let Some(sequence) = sequence {
+                last_sequence.store(sequence, Ordering::Release);
+            }
         }
 
         Ok(())
     }
 
-    pub fn init_reader(&self) -> io::Result<()> {
-        let actions = self.actions.clone();
-        let should_drop = self.should_drop.clone();
-        let stream = self.stream.try_clone()?;
-        thread::spawn(move || Self::reader_loop(stream, &actions, &should_drop));
+    pub fn init_reader(&mut self) -> error::Result<()> {
+        let Some(reader) = self.reader.take() else {
+            return Ok(());
+        };
+        let (actions, receiver) = mpsc::sync_channel(ACTION_CAPACITY);
+        self.actions = Some(receiver);
+        let connected = self.connected.clone();
+        let epoch = self.epoch;
+        let last_seen = self.last_seen.clone();
+        let sequence = self.sequence.clone();
+        self.reader_thread = Some(thread::spawn(move || {
+            let result = Self::reader_loop(reader, actions, epoch, &last_seen, &sequence);
+            connected.store(false, Ordering::Release);
+            result
+        }));
         Ok(())
     }
 
-    pub fn send_input(&mut self, input: char) -> io::Result<()> {
-        self.stream.write(&[input as u8])?;
-        Ok(())
+    /// Whether the connection's still up, as far as the Socket can tell.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Acquire)
     }
 
-    pub fn create_session(&mut self) -> io::Result<u16> {
-        self.stream.write("Create".as_bytes())?;
+    /// When the server was last heard from.
+    pub fn last_seen(&self) -> Instant {
+        self.epoch + Duration::from_millis(self.last_seen.load(Ordering::Acquire))
+    }
 
-        let mut buffer = [0u8; 2];
+    /// Sequence number of the last Broadcast from the session, a Reconnect picks up after it.
+    pub fn sequence(&self) -> u32 {
+        self.sequence.load(Ordering::Acquire)
+    }
 
-        let size = self.stream.read(&mut buffer)?;
+    /// Lets the server know the client's still there, at most once every HEARTBEAT_INTERVAL.
+    pub fn heartbeat(&mut self) {
+        if self.last_heartbeat.elapsed() < HEARTBEAT_INTERVAL {
+            return;
+        }
 
-        if size < 2 {
-            return Err(Error::new(
-                ErrorKind::OutOfMemory,
-                "Session Couldn't be created.",
-            ));
+        self.last_heartbeat = Instant::now();
+
+        // A failure marks the Socket as disconnected, that's all there is to do about it
+        let _ = self.send(Message::Heartbeat);
+    }
+
+    /// Why the reader thread stopped, None while it's still running.
+    pub fn reader_error(&mut self) -> Option<Error> {
+        if !self.reader_thread.as_ref()?.is_finished() {
+            return None;
         }
 
-        Ok(u16::from_be_bytes(buffer))
+        Some(match self.reader_thread.take()?.join() {
+            Ok(Ok(())) => Error::Network(io::Error::new(
+                ErrorKind::ConnectionAborted,
+                "The server closed the connection.",
+            )),
+            Ok(Err(err)) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                Error::Network(io::Error::new(
+                    ErrorKind::TimedOut,
+                    format!(
+                        "The server didn't answer for {} seconds.",
+                        HEARTBEAT_TIMEOUT.as_secs()
+                    ),
+                ))
+            }
+            Ok(Err(err)) => read_error(err),
+            Err(_) => Error::Network(io::Error::other("Reading from the server crashed.")),
+        })
     }
 
-    /// It'll join an already existing session and return the position of the player.
-    pub fn join_session(&mut self, session_token: impl Into<u16>) -> io::Result<u8> {
-        let be_bytes = session_token.into().to_be_bytes();
+    fn send(&mut self, message: Message) -> error::Result<()> {
+        protocol::write_message(&mut self.stream, &message).map_err(|err| {
+            self.connected.store(false, Ordering::Release);
+            Error::Network(err)
+        })
+    }
+
+    /// Waits for the server's answer to a request.
+    fn reply(&mut self) -> error::Result<Option<Message>> {
+        let Some(reader) = &mut self.reader else {
+            return Ok(None);
+        };
+
+        let reply = reader.read_message().map_err(read_error)?;
+
+        self.last_seen
+            .store(self.epoch.elapsed().as_millis() as u64, Ordering::Release);
 
-        let buffer = [b'J', b'o', b'i', b'n', be_bytes[0], be_bytes[1]];
+        Ok(reply)
+    }
+
+    pub fn send_input(&mut self, edit: Edit) -> error::Result<()> {
+        self.send(Message::Input(edit))
+    }
 
-        self.stream.write(&buffer)?;
+    /// Creates a session for players with the word list that has this checksum.
+    pub fn create_session(&mut self, dictionary: u64) -> error::Result<u16> {
+        self.send(Message::Create(dictionary))?;
 
-        let mut buffer = [0u8; 1];
+        match self.reply()? {
+            Some(Message::Created(session_token)) => {
+                self.in_session = true;
+                Ok(session_token)
+            }
+            _ => Err(Error::Protocol(io::Error::new(
+                ErrorKind::InvalidData,
+                "Session Couldn't be created.",
+            ))),
+        }
+    }
 
-        if self.stream.read(&mut buffer)? < 1 {
-            return Err(Error::new(
+    /// It'll join an already existing session and return the position of the player.
+    pub fn join_session(
+        &mut self,
+        session_token: impl Into<u16>,
+        dictionary: u64,
+    ) -> error::Result<u8> {
+        self.send(Message::Join(session_token.into(), dictionary))?;
+
+        // The server hangs up on players joining a session that doesn't exist
+        match self.reply()? {
+            Some(Message::Joined(position)) => {
+                self.in_session = true;
+                Ok(position)
+            }
+            Some(Message::DictionaryMismatch) => Err(Error::Dictionary(io::Error::new(
+                ErrorKind::InvalidData,
+                "The session plays with a different word list than yours.",
+            ))),
+            _ => Err(Error::Protocol(io::Error::new(
                 ErrorKind::ConnectionAborted,
                 "Couldn't Join Session.",
-            ));
+            ))),
         }
+    }
 
-        Ok(buffer[0])
+    /// Gets back into a session after the connection dropped, with the same position. The
+    /// server sends every Broadcast after the `sequence` one again.
+    pub fn rejoin_session(
+        &mut self,
+        session_token: u16,
+        position: u8,
+        sequence: u32,
+    ) -> error::Result<()> {
+        self.send(Message::Rejoin(session_token, position, sequence))?;
+
+        // The server hangs up if the session's over or went on without the player
+        match self.reply()? {
+            Some(Message::Rejoined) => {
+                self.in_session = true;
+                self.sequence.store(sequence, Ordering::Release);
+                Ok(())
+            }
+            _ => Err(Error::Protocol(io::Error::new(
+                ErrorKind::ConnectionAborted,
+                "The session is over or went on without you.",
+            ))),
+        }
     }
 
-    pub fn actions(&self) -> MutexGuard<Vec<Action>> {
-        self.actions.lock().unwrap()
+    /// The next Action that arrived no later than `until`, never waits for the reader thread.
+    pub fn next_action(&mut self, until: Instant) -> Option<Action> {
+        if self.pending.is_none() {
+            // Empty, or the reader thread's done and is_connected says so
+            self.pending = self.actions.as_ref()?.try_recv().ok();
+        }
+
+        match self.pending {
+            Some((arrived, action)) if arrived <= until => {
+                self.pending = None;
+                Some(action)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Tries to get back into the session in the background after the connection dropped, the
+/// game keeps running meanwhile. Dropping it stops trying once the current attempt is over.
+pub struct Reconnect {
+    pub since: Instant,
+    // Why the connection dropped, if the Socket could tell
+    pub cause: Option<Error>,
+    // Set once the player gave up on the session, checked between attempts
+    cancelled: Arc<AtomicBool>,
+    // Only None once finished
+    attempt: Option<JoinHandle<error::Result<Socket>>>,
+}
+
+impl Drop for Reconnect {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Release);
+
+        // Cuts the pause between two attempts short
+        if let Some(attempt) = &self.attempt {
+            attempt.thread().unpark();
+        }
+    }
+}
+
+impl Reconnect {
+    pub fn start(
+        addr: String,
+        session_token: u16,
+        position: u8,
+        sequence: u32,
+        cause: Option<Error>,
+    ) -> Self {
+        let since = Instant::now();
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let is_cancelled = cancelled.clone();
+        let attempt = thread::spawn(move || {
+            while !is_cancelled.load(Ordering::Acquire) {
+                // Should the player give up meanwhile, the Socket's dropped along with the result
+                // and leaves the session again
+                let left = RECONNECT_TIMEOUT.saturating_sub(since.elapsed());
+
+                match Socket::connect_timeout(&addr, left).and_then(|mut socket| {
+                    socket.rejoin_session(session_token, position, sequence)?;
+                    Ok(socket)
+                }) {
+                    Ok(socket) => return Ok(socket),
+                    // The server's there but won't take the player back, no point in trying again
+                    Err(Error::Protocol(err)) => return Err(Error::Disconnected(err)),
+                    Err(_) => (),
+                }
+
+                if since.elapsed() + RECONNECT_INTERVAL >= RECONNECT_TIMEOUT {
+                    return Err(Error::Disconnected(io::Error::new(
+                        ErrorKind::TimedOut,
+                        format!(
+                            "Couldn't get back into the session within {} seconds.",
+                            RECONNECT_TIMEOUT.as_secs()
+                        ),
+                    )));
+                }
+
+                thread::park_timeout(RECONNECT_INTERVAL);
+            }
+
+            Err(Error::Disconnected(io::Error::new(
+                ErrorKind::Interrupted,
+                "Stopped getting back into the session.",
+            )))
+        });
+
+        Self {
+            since,
+            cause,
+            cancelled,
+            attempt: Some(attempt),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.attempt.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+
+    /// Waits for the attempt to end, the Socket still needs its reader started.
+    pub fn finish(mut self) -> error::Result<Socket> {
+        let crashed = || {
+            Err(Error::Disconnected(io::Error::other(
+                "Reconnecting crashed.",
+            )))
+        };
+
+        match self.attempt.take() {
+            Some(attempt) => attempt.join().unwrap_or_else(|_| crashed()),
+            None => crashed(),
+        }
     }
 }
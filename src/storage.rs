@@ -0,0 +1,187 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const HISTORY_FILE: &str = "history.tsv";
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Mode {
+    SinglePlayer,
+    MultiPlayer,
+}
+
+impl Mode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Mode::SinglePlayer => "single",
+            Mode::MultiPlayer => "multi",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "single" => Some(Mode::SinglePlayer),
+            "multi" => Some(Mode::MultiPlayer),
+            _ => None,
+        }
+    }
+}
+
+/// A finished run, stored as a single tab separated line in the history file
+#[derive(Clone)]
+pub struct Run {
+    // Seconds since the unix epoch
+    pub timestamp: u64,
+    pub mode: Mode,
+    pub seed: u64,
+    pub wpm: f32,
+    pub accuracy: f32,
+    pub words: u32,
+}
+
+impl Run {
+    pub fn new(mode: Mode, seed: u64, wpm: f32, accuracy: f32, words: u32) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            timestamp,
+            mode,
+            seed,
+            wpm,
+            accuracy,
+            words,
+        }
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{:.2}\t{:.2}\t{}\n",
+            self.timestamp,
+            self.mode.as_str(),
+            self.seed,
+            self.wpm,
+            self.accuracy,
+            self.words
+        )
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+
+        Some(Self {
+            timestamp: fields.next()?.parse().ok()?,
+            mode: Mode::parse(fields.next()?)?,
+            seed: fields.next()?.parse().ok()?,
+            wpm: fields.next()?.parse().ok()?,
+            accuracy: fields.next()?.parse().ok()?,
+            words: fields.next()?.parse().ok()?,
+        })
+    }
+
+    pub fn mode_name(&self) -> &'static str {
+        match self.mode {
+            Mode::SinglePlayer => "Single Player",
+            Mode::MultiPlayer => "Multiplayer",
+        }
+    }
+
+    /// The UTC date of the run formatted as YYYY-MM-DD.
+    pub fn date(&self) -> String {
+        // Howard Hinnant's civil_from_days
+        let z = (self.timestamp / 86400) as i64 + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+
+    /// Column titles lined up with `row`.
+    pub fn header() -> String {
+        format!(
+            "{:<4}{:<12}{:<15}{:>6}{:>10}{:>7}  {}",
+            "#", "Date", "Mode", "WPM", "Accuracy", "Words", "Seed"
+        )
+    }
+
+    /// The run as a line of a table, `rank` being its place in it.
+    pub fn row(&self, rank: usize) -> String {
+        format!(
+            "{:<4}{:<12}{:<15}{:>6.0}{:>9.1}%{:>7}  {}",
+            rank,
+            self.date(),
+            self.mode_name(),
+            self.wpm,
+            self.accuracy,
+            self.words,
+            self.seed
+        )
+    }
+}
+
+/// $XDG_DATA_HOME/tip, falling back to ~/.local/share/tip (%APPDATA%\tip on Windows).
+pub fn data_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        return env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("tip"));
+    }
+
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .map(|dir| dir.join("tip"))
+}
+
+fn history_path() -> io::Result<PathBuf> {
+    data_dir()
+        .map(|dir| dir.join(HISTORY_FILE))
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "Couldn't find a data directory."))
+}
+
+/// Appends the run to the history file.
+pub fn record(run: &Run) -> io::Result<()> {
+    let path = history_path()?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(run.to_line().as_bytes())
+}
+
+/// Every recorded run, oldest first. Lines that can't be parsed are skipped.
+pub fn history() -> io::Result<Vec<Run>> {
+    let contents = match fs::read_to_string(history_path()?) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+
+    Ok(contents.lines().filter_map(Run::parse).collect())
+}
+
+/// The best runs by WPM, highest first.
+pub fn high_scores(limit: usize) -> io::Result<Vec<Run>> {
+    let mut runs = history()?;
+
+    runs.sort_by(|run_a, run_b| run_b.wpm.total_cmp(&run_a.wpm));
+    runs.truncate(limit);
+
+    Ok(runs)
+}
@@ -1,25 +1,167 @@
-use super::socket::Socket;
-use std::time::Instant;
+use super::{
+    cli::Args,
+    config::{Config, KeyBindings, Theme},
+    dictionary::Filter,
+    error::Error,
+    protocol::Edit,
+    socket::{Reconnect, Socket},
+    storage::{self, Mode, Run},
+};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+use unicode_segmentation::UnicodeSegmentation;
 
 pub struct State {
+    // The command line arguments, they take precedence over the config when it's reloaded
+    pub args: Args,
     pub columns: u16,
     pub rows: u16,
     pub sock_addr: String,
     pub screen: Screen,
     pub players: Vec<Player>,
     pub dictionary: Vec<Word>,
-    pub instant: Instant,
-    pub last_instant: u128,
+    // The validated word list every run's dictionary is shuffled from
+    pub word_list: Vec<Word>,
+    // Applied to the word list in Single Player and new multiplayer sessions
+    pub filter: Filter,
+    // The Filter of the current multiplayer session, which might've been created by someone else
+    pub session_filter: Filter,
+    // Used to shuffle the dictionary, the session token in a multiplayer session
+    pub seed: u64,
+    // Set with --seed, makes every Single Player run use the same words
+    pub fixed_seed: Option<u64>,
+    // Multiplier of how fast the words move in Single Player
+    pub speed: f32,
+    // Tune the Single Player pace to the player's typing instead of using levels
+    pub adaptive: bool,
+    pub nickname: Option<String>,
+    pub keys: KeyBindings,
+    pub theme: Theme,
+    pub high_scores: Vec<Run>,
+    // Pace of the current Single Player run, updated every tick
+    pub pace: Pace,
+    // When the simulation last caught up with the clock and the game time it's still behind
+    pub last_tick: Instant,
+    pub lag: Duration,
+    // Game time since the words last moved in Single Player
+    pub since_step: Duration,
     pub current_player: usize,
     pub session_token: Option<u16>,
     pub socket: Option<Socket>,
-    pub err: Option<Box<dyn std::error::Error>>,
+    // Set while the connection to the session is down and tip tries to get back in
+    pub reconnect: Option<Reconnect>,
+    // Shown on the error screen, along with the option to retry whatever failed
+    pub error: Option<Error>,
+    pub retry: Option<Task>,
+    // Set to leave the main loop, the terminal is restored on the way out
+    pub quit: bool,
+}
+
+impl State {
+    /// The main menu with the default settings, load_settings fills in the rest.
+    pub fn new(args: Args, columns: u16, rows: u16) -> Self {
+        let config = Config::default();
+
+        Self {
+            args,
+            columns,
+            rows,
+            dictionary: vec![],
+            word_list: vec![],
+            filter: Filter::default(),
+            session_filter: Filter::default(),
+            seed: 0,
+            high_scores: vec![],
+            fixed_seed: None,
+            speed: config.speed,
+            adaptive: config.adaptive,
+            nickname: config.nickname,
+            keys: config.keys,
+            theme: config.theme,
+            sock_addr: config.server,
+            screen: Screen::Main,
+            players: vec![],
+            pace: Pace::of_level(1, 1.0),
+            last_tick: Instant::now(),
+            lag: Duration::ZERO,
+            since_step: Duration::ZERO,
+            current_player: 0,
+            session_token: None,
+            socket: None,
+            reconnect: None,
+            error: None,
+            retry: None,
+            quit: false,
+        }
+    }
+
+    /// Ends the current player's run, records it in the history and shows the results.
+    pub fn finish_run(&mut self) {
+        let run = self.end_run();
+
+        if let Err(err) = storage::record(&run) {
+            self.fail(Error::Storage(err), Some(Task::Record(run)));
+        }
+    }
+
+    /// Ends the current player's run early and shows the results without recording it, a run
+    /// quit after a few keystrokes would top the high scores.
+    pub fn quit_run(&mut self) {
+        self.end_run();
+    }
+
+    fn end_run(&mut self) -> Run {
+        let mode = if self.session_token.is_some() {
+            Mode::MultiPlayer
+        } else {
+            Mode::SinglePlayer
+        };
+
+        let stats = &mut self.players[self.current_player].stats;
+
+        stats.finish();
+
+        let run = Run::new(
+            mode,
+            self.seed,
+            stats.net_wpm(),
+            stats.accuracy(),
+            stats.completed_words,
+        );
+
+        self.screen = Screen::Results;
+
+        run
+    }
+
+    /// Shows the error screen, retrying runs the Task again.
+    pub fn fail(&mut self, error: Error, retry: Option<Task>) {
+        self.error = Some(error);
+        self.retry = retry;
+        self.screen = Screen::Error;
+    }
+}
+
+/// Something the player started that can fail and be retried from the error screen
+#[derive(Clone)]
+pub enum Task {
+    // Reload the config file and the word list
+    Settings,
+    // Start a Single Player run with the given seed, or a random one
+    SinglePlayer(Option<u64>),
+    Host,
+    Join(String),
+    HighScores,
+    // Save a finished run in the history
+    Record(Run),
 }
 
 /// Used in Multiplayer to determine what kind of data is received
 #[derive(Copy, Clone)]
 pub enum Action {
-    Input((usize, char)),
+    Input((usize, Edit)),
     Join(u8),
     Left(usize),
     Forward,
@@ -32,6 +174,10 @@ pub enum Screen {
     MultiPlayer,
     Join,
     Loading,
+    Results,
+    HighScores,
+    // Shows State::error until it's retried or dismissed
+    Error,
 }
 
 #[derive(Clone)]
@@ -49,9 +195,21 @@ impl Word {
             y,
         }
     }
+
+    /// Whether the input starts with the whole word, compared grapheme by grapheme so that
+    /// "e" doesn't count as typed for an "é" made up of an "e" and a combining accent.
+    pub fn is_typed_by(&self, input: &str) -> bool {
+        let mut typed = input.graphemes(true);
+
+        self.value
+            .graphemes(true)
+            .all(|grapheme| typed.next() == Some(grapheme))
+    }
 }
 
-#[derive(Default)]
+/// How many words a Player can let escape before the game is over
+pub const LIVES: u8 = 3;
+
 pub struct Player {
     // Used for ordering the players positions on screen in a multiplayer session
     pub sort_position: u8,
@@ -59,4 +217,301 @@ pub struct Player {
     pub position: usize,
     pub input: String,
     pub current_player: bool,
+    pub stats: Stats,
+    // Lost whenever a word escapes the screen
+    pub lives: u8,
+    // When the keystrokes of the last ROLLING_WINDOW were typed and whether they were correct
+    pub recent_keystrokes: VecDeque<(Instant, bool)>,
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Self {
+            sort_position: 0,
+            position: 0,
+            input: String::new(),
+            current_player: false,
+            stats: Stats::default(),
+            lives: LIVES,
+            recent_keystrokes: VecDeque::new(),
+        }
+    }
+}
+
+impl Player {
+    pub fn is_alive(&self) -> bool {
+        self.lives > 0
+    }
+
+    /// Moves the Player onto the next word, costing a life if the current one escaped.
+    pub fn next_word(&mut self, completed: bool) {
+        if completed {
+            self.stats.completed_words += 1;
+        } else {
+            self.stats.missed_words += 1;
+            self.lives = self.lives.saturating_sub(1);
+        }
+        self.input.clear();
+        self.position += 1;
+    }
+
+    /// Applies an Edit to the input, the same way for the current player and everyone else.
+    pub fn edit(&mut self, edit: Edit) {
+        match edit {
+            Edit::Char(c) => self.input.push(c),
+            Edit::Backspace => {
+                self.input.pop();
+            }
+            Edit::DeleteWord => {
+                let end = self.input.trim_end().len();
+                self.input.truncate(end);
+                // Back to the last whitespace, like Ctrl+W in a shell
+                let start = self
+                    .input
+                    .trim_end_matches(|c: char| !c.is_whitespace())
+                    .len();
+                self.input.truncate(start);
+            }
+            Edit::Clear => self.input.clear(),
+        }
+    }
+
+    pub fn record_keystroke(&mut self, correct: bool) {
+        if correct {
+            self.stats.correct_keystrokes += 1;
+        } else {
+            self.stats.incorrect_keystrokes += 1;
+        }
+
+        let now = Instant::now();
+
+        self.recent_keystrokes.push_back((now, correct));
+
+        while let Some(&(typed, _)) = self.recent_keystrokes.front() {
+            if now - typed <= ROLLING_WINDOW {
+                break;
+            }
+            self.recent_keystrokes.pop_front();
+        }
+    }
+
+    /// WPM of the correct keystrokes over the last ROLLING_WINDOW, or the whole run if it's shorter.
+    pub fn rolling_wpm(&self) -> f32 {
+        let window = self.stats.elapsed().min(ROLLING_WINDOW).as_secs_f32() / 60.0;
+        if window == 0.0 {
+            return 0.0;
+        }
+
+        let cutoff = Instant::now().checked_sub(ROLLING_WINDOW);
+
+        let correct = self
+            .recent_keystrokes
+            .iter()
+            .filter(|&&(typed, correct)| correct && cutoff.is_none_or(|cutoff| typed >= cutoff))
+            .count();
+
+        correct as f32 / 5.0 / window
+    }
+
+    /// Share of incorrect keystrokes among the recent ones, from 0 to 1.
+    pub fn rolling_error_rate(&self) -> f32 {
+        if self.recent_keystrokes.is_empty() {
+            return 0.0;
+        }
+
+        let incorrect = self
+            .recent_keystrokes
+            .iter()
+            .filter(|(_, correct)| !correct)
+            .count();
+
+        incorrect as f32 / self.recent_keystrokes.len() as f32
+    }
+}
+
+/// How far back the rolling WPM and error rate look
+pub const ROLLING_WINDOW: Duration = Duration::from_secs(15);
+
+/// Every this many completed words a Single Player run goes up a level
+pub const WORDS_PER_LEVEL: u32 = 10;
+
+pub const MAX_LEVEL: u32 = 10;
+
+impl Player {
+    /// The Single Player level, starting at 1.
+    pub fn level(&self) -> u32 {
+        (1 + self.stats.completed_words / WORDS_PER_LEVEL).min(MAX_LEVEL)
+    }
+}
+
+/// How many words are on screen at once at the start of a run, and always in multiplayer
+pub const VISIBLE_WORDS: usize = 4;
+
+/// How often the words move and how many are on screen at once in Single Player
+pub struct Pace {
+    pub step_interval: Duration,
+    pub visible_words: usize,
+}
+
+impl Pace {
+    /// Every level the words move 15% more often, every third level another word shows up.
+    pub fn of_level(level: u32, speed: f32) -> Self {
+        let level = level.clamp(1, MAX_LEVEL);
+        Self {
+            step_interval: Duration::from_secs_f32(0.5 / speed * 0.85f32.powi(level as i32 - 1)),
+            visible_words: VISIBLE_WORDS + (level as usize - 1) / 3,
+        }
+    }
+
+    /// Keeps the player at the edge of their ability: the last word on screen moves a column
+    /// per step, so it crosses the screen just about when all visible words could be typed at
+    /// the rolling WPM. Mistakes slow the words down and take words off the screen.
+    pub fn adaptive(player: &Player, columns: u16, speed: f32) -> Self {
+        // Not enough keystrokes to tell yet
+        if player.recent_keystrokes.len() < ADAPTIVE_MIN_KEYSTROKES {
+            return Self::of_level(1, speed);
+        }
+
+        let wpm = player.rolling_wpm().max(ADAPTIVE_MIN_WPM);
+        let error_rate = player.rolling_error_rate();
+
+        let visible_words = if error_rate > 0.1 {
+            VISIBLE_WORDS
+        } else {
+            (VISIBLE_WORDS + (wpm / 40.0) as usize).min(7)
+        };
+
+        // An average word plus the keystroke to get to the next one
+        let chars = visible_words as f32 * 6.0;
+        let chars_per_second = wpm * 5.0 / 60.0;
+
+        let secs =
+            chars / chars_per_second / f32::from(columns.max(1)) * (1.0 + 2.0 * error_rate) / speed;
+
+        Self {
+            step_interval: Duration::from_secs_f32(secs),
+            visible_words,
+        }
+    }
+}
+
+const ADAPTIVE_MIN_KEYSTROKES: usize = 10;
+
+// Even a struggling player gets words slow enough to type them
+const ADAPTIVE_MIN_WPM: f32 = 10.0;
+
+/// Per-run typing statistics of a Player
+#[derive(Default, Clone, Copy)]
+pub struct Stats {
+    pub correct_keystrokes: u32,
+    pub incorrect_keystrokes: u32,
+    pub completed_words: u32,
+    pub missed_words: u32,
+    pub started: Option<Instant>,
+    pub finished: Option<Instant>,
+}
+
+impl Stats {
+    pub fn start(&mut self) {
+        *self = Self {
+            started: Some(Instant::now()),
+            ..Default::default()
+        };
+    }
+
+    pub fn finish(&mut self) {
+        if self.finished.is_none() {
+            self.finished = Some(Instant::now());
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        match self.started {
+            Some(started) => self.finished.unwrap_or_else(Instant::now) - started,
+            None => Duration::ZERO,
+        }
+    }
+
+    fn minutes(&self) -> f32 {
+        self.elapsed().as_secs_f32() / 60.0
+    }
+
+    /// Every 5 keystrokes count as a word, regardless of whether they were correct.
+    pub fn gross_wpm(&self) -> f32 {
+        let minutes = self.minutes();
+        if minutes == 0.0 {
+            return 0.0;
+        }
+        (self.correct_keystrokes + self.incorrect_keystrokes) as f32 / 5.0 / minutes
+    }
+
+    /// Gross WPM minus the errors made per minute.
+    pub fn net_wpm(&self) -> f32 {
+        let minutes = self.minutes();
+        if minutes == 0.0 {
+            return 0.0;
+        }
+        (self.gross_wpm() - self.incorrect_keystrokes as f32 / minutes).max(0.0)
+    }
+
+    /// Percentage of correct keystrokes.
+    pub fn accuracy(&self) -> f32 {
+        let total = self.correct_keystrokes + self.incorrect_keystrokes;
+        if total == 0 {
+            return 0.0;
+        }
+        self.correct_keystrokes as f32 / total as f32 * 100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edited(input: &str, edit: Edit) -> String {
+        let mut player = Player {
+            input: input.to_owned(),
+            ..Default::default()
+        };
+        player.edit(edit);
+        player.input
+    }
+
+    #[test]
+    fn delete_word() {
+        for (input, expected) in [
+            ("", ""),
+            ("word", ""),
+            ("two words", "two "),
+            ("trailing  ", ""),
+            ("two words  ", "two "),
+            ("a-b", ""),
+            ("two-part word", "two-part "),
+            ("don't", ""),
+            ("café", ""),
+            ("end.", ""),
+            ("tab\tseparated", "tab\t"),
+        ] {
+            assert_eq!(edited(input, Edit::DeleteWord), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn backspace_and_clear() {
+        assert_eq!(edited("ab", Edit::Backspace), "a");
+        assert_eq!(edited("aß", Edit::Backspace), "a");
+        assert_eq!(edited("", Edit::Backspace), "");
+        assert_eq!(edited("ab cd", Edit::Clear), "");
+        assert_eq!(edited("ab", Edit::Char('c')), "abc");
+    }
+
+    #[test]
+    fn typed_by_whole_graphemes() {
+        // "é" as an "e" followed by a combining acute accent
+        let word = Word::new("cafe\u{301}", 0, 0);
+
+        assert!(word.is_typed_by("cafe\u{301}"));
+        assert!(!word.is_typed_by("cafe"));
+        assert!(!word.is_typed_by("caf"));
+    }
 }